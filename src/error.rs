@@ -0,0 +1,85 @@
+use std::fmt;
+
+use crate::parse::ParseError;
+
+/// Errors that can occur while parsing, compiling, instantiating or
+/// evaluating a formula.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    /// The formula could not be parsed
+    ParseError(ParseError),
+    /// The formula references one or more variables that were not declared
+    UnknownVariable(Vec<UnknownIdentifier>),
+    /// The formula uses an operator or AST node that cannot be compiled
+    UnsupportedOperator(String),
+    /// wasmer failed to compile a module
+    WasmCompile(String),
+    /// wasmer failed to instantiate a module or resolve one of its exports
+    WasmInstantiate(String),
+    /// A call into webassembly trapped
+    Trap(String),
+    /// A value of the wrong type was supplied
+    TypeMismatch(String),
+    /// A value or batch is too large, or the columns of a batch
+    /// differ in length
+    InvalidInput(String),
+    /// An integer was divided by zero, or its remainder taken
+    DivisionByZero,
+    /// A regex literal is not a valid regular expression
+    InvalidRegex(String),
+    /// Writing debug output failed
+    Io(String),
+}
+
+/// An identifier referenced by a formula that was not declared
+/// with ```Context::define_var```.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnknownIdentifier {
+    /// Name of the identifier
+    pub name: String,
+    /// Byte offset of the identifier within the formula source, if
+    /// the source was supplied to the compiler
+    pub offset: Option<usize>,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::ParseError(e) => write!(f, "parse error: {}", e),
+            Error::UnknownVariable(idents) => {
+                write!(f, "unknown variable")?;
+                if idents.len() > 1 {
+                    write!(f, "s")?;
+                }
+                for (i, ident) in idents.iter().enumerate() {
+                    let sep = if i == 0 { ": " } else { ", " };
+                    match ident.offset {
+                        Some(offset) => write!(f, "{}{} at {}", sep, ident.name, offset)?,
+                        None => write!(f, "{}{}", sep, ident.name)?,
+                    }
+                }
+                Ok(())
+            }
+            Error::UnsupportedOperator(msg) => write!(f, "unsupported operator: {}", msg),
+            Error::WasmCompile(msg) => write!(f, "wasm compilation failed: {}", msg),
+            Error::WasmInstantiate(msg) => write!(f, "wasm instantiation failed: {}", msg),
+            Error::Trap(msg) => write!(f, "wasm trap: {}", msg),
+            Error::TypeMismatch(msg) => write!(f, "type mismatch: {}", msg),
+            Error::InvalidInput(msg) => write!(f, "invalid input: {}", msg),
+            Error::DivisionByZero => write!(f, "integer division by zero"),
+            Error::InvalidRegex(msg) => write!(f, "invalid regex: {}", msg),
+            Error::Io(msg) => write!(f, "I/O error: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<ParseError> for Error {
+    fn from(e: ParseError) -> Error {
+        Error::ParseError(e)
+    }
+}
+
+/// Result type used throughout wormula
+pub type Result<T> = std::result::Result<T, Error>;
@@ -1,10 +1,23 @@
 use parity_wasm::builder;
 use parity_wasm::elements;
 use parity_wasm::elements::*;
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::cmp::Ordering;
 use std::collections::HashMap;
+use std::convert::TryFrom;
 use std::io::Write;
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
 
+use crate::error::{Error, Result, UnknownIdentifier};
+use crate::interpreter::{Interpreter, Program};
+use crate::parse::parse_formula;
+use crate::record::Value;
+use crate::simplify::simplify;
 use crate::term::*;
 
 static FUN_MAKE_STATE: u32 = 0;
@@ -21,104 +34,524 @@ static FUN_GET_STRING_BUF: u32 = 10;
 static FUN_MAKE_STRING: u32 = 11;
 static FUN_MAKE_REGEX: u32 = 12;
 
+/// Number of functions imported from wormrtl; the formula's own
+/// functions are numbered starting from here.
+static NUM_IMPORTED_FUNS: u32 = 13;
+static FUN_EVAL: u32 = 14; // NUM_IMPORTED_FUNS + 1
+static FUN_COMPARE_TEXT: u32 = 19; // NUM_IMPORTED_FUNS + 6
+
+/// Global holding the address of the typed variables' storage
+static GLOBAL_NATIVE_BASE: u32 = 0;
+/// Global set to an error code by a failed evaluation, see
+/// ```take_error```
+static GLOBAL_ERROR: u32 = 1;
+/// Global holding the address of the value cells, 16 bytes per slot:
+/// the type of the slot's value as one of the VALUE_ tags (i32),
+/// followed by the value (i64). f64 are stored as their bits, texts
+/// as their address in the lower and their length in the upper half.
+/// The host keeps the cells of the variables that orderings and
+/// arithmetic read up to date, as the runtime library can neither
+/// order values nor compute with them.
+static GLOBAL_CELLS: u32 = 2;
+
+/// Types of the values in the value cells
+static VALUE_I64: i32 = 1;
+static VALUE_F64: i32 = 2;
+static VALUE_TEXT: i32 = 3;
+
+/// Results of the eval function: formulas evaluate to true, false or
+/// unknown, the latter if they depend on null values
+static TRUTH_FALSE: i32 = 0;
+static TRUTH_UNKNOWN: i32 = 1;
+static TRUTH_TRUE: i32 = 2;
+
+/// Error codes stored in GLOBAL_ERROR
+static ERROR_DIVISION_BY_ZERO: i32 = 1;
+static ERROR_NOT_A_NUMBER: i32 = 2;
+
+/// Column kinds understood by the eval_batch function
+static COLUMN_F64: i32 = 0;
+static COLUMN_I64: i32 = 1;
+static COLUMN_STRING: i32 = 2;
+/// Column of 8 byte values copied to the address in the descriptor's
+/// slot field, used for typed variables
+static COLUMN_NATIVE: i32 = 3;
+
+/// Size of a column descriptor passed to eval_batch:
+/// slot (i64), kind (i32), pointer to the column data (i32), address
+/// of the slot's value cell (i32), the VALUE_ tag of the column (i32),
+/// pointer to the null mask, one byte per row (i32, 0 if the column
+/// has none) and address of the slot's null flag (i32)
+static COLUMN_DESC_SIZE: usize = 32;
+
 /// Context represents an evaluation context that can be used to
 /// parse and execute one or more formulas.
 pub struct Context<'a> {
-    rtl_module: wasmer_runtime::Module,
+    /// The AOT compiled runtime library; None for the interpreter backend
+    rtl_module: Option<wasmer_runtime::Module>,
     variables: HashMap<&'a str, i64>,
+    /// Declared type of typed variables, by slot
+    types: HashMap<i64, Type>,
+    /// Type and native storage cell of natively stored typed
+    /// variables, by slot
+    native: HashMap<i64, (Type, u32)>,
+    /// The slot of the next declared variable. Each formula numbers
+    /// its temporaries from here on, see ```Compilation```.
+    locals: i64,
+    lenient: bool,
+    dump_dir: Option<PathBuf>,
+}
+
+/// The state of compiling a single formula. It only borrows the
+/// context, so the formulas compiled by a context neither share nor
+/// accumulate slots: each numbers its temporaries right after the
+/// declared variables.
+struct Compilation<'c, 'a: 'c> {
+    context: &'c Context<'a>,
     instructions: Vec<Instruction>,
     idx_state: Option<u32>,
-    idx_result: Option<u32>,
     idx_string: Option<u32>,
     idx_mem_buf_ptr: Option<u32>,
-    idx_var_result: Option<u32>,
+    /// Number of i64 locals of the eval function
+    eval_temps: u32,
     locals: i64,
-    reserved_slots: i64,
+    /// Slots of the undeclared variables of the formula in lenient
+    /// mode
+    lenient_slots: Vec<i64>,
+    /// Slots of the variables whose value cells the formula reads
+    dynamic_slots: Vec<i64>,
 }
 
-/// Variable represents a variable of a specific instance of a formula.
-pub struct Variable<'b, 'a: 'b> {
+/// Variable represents an untyped variable of a specific instance of
+/// a formula, which can be set to values of any type. Variables
+/// declared with a type are accessed through ```TypedVariable```.
+pub struct Variable<'b> {
     idx: i64,
-    ct: &'b InstantiatedTerm<'a, 'b>,
+    ty: Option<Type>,
+    ct: &'b InstantiatedTerm,
 }
 
-impl<'a, 'b> Variable<'a, 'b> {
+impl<'b> Variable<'b> {
     /// Sets the value of the references variable to ```string```
-    pub fn set_string(&mut self, string: &str) {
-        let string_bytes = string.as_bytes();
-        let alloc_buffer = self
-            .ct
-            .alloc_string_call
-            .call(string_bytes.len() as i32)
-            .expect("call alloc_string");
-        let ptr = self
-            .ct
-            .get_string_buf_call
-            .call(alloc_buffer)
-            .expect("call get_string_buf") as usize;
-        let view = self.ct.memory.view::<u8>();
-        for i in 0..string_bytes.len() {
-            view[ptr + i].set(string_bytes[i]);
-        }
-        let s = self
-            .ct
-            .make_string_call
-            .call(
-                self.ct.ct.fm_init_res,
-                self.idx,
-                alloc_buffer,
-                string_bytes.len() as i32,
-            )
-            .expect("make_string");
-        if s != self.idx {
-            eprintln!("s != self.idx, what gives?");
-        }
+    pub fn set_string(&mut self, string: &str) -> Result<()> {
+        self.check_type(Type::String)?;
+        self.ct.evaluator.set_string(self.idx, string)
     }
 
     /// Sets the value of the references variable to ```intval```
-    pub fn set_i64(&mut self, intval: i64) {
-        self.ct
-            .make_i64_call
-            .call(self.ct.ct.fm_init_res, self.idx, intval)
-            .expect("call make_i64");
+    pub fn set_i64(&mut self, intval: i64) -> Result<()> {
+        self.check_type(Type::I64)?;
+        self.ct.evaluator.set_i64(self.idx, intval)
     }
 
     /// Sets the value of the references variable to ```fval```
-    pub fn set_f64(&mut self, fval: f64) {
-        self.ct
-            .make_f64_call
-            .call(self.ct.ct.fm_init_res, self.idx, fval)
-            .expect("call make_f64");
-    }
-}
-
-/// CompiledTerm represents a compiled formula
-pub struct CompiledTerm<'b, 'a: 'b> {
-    //    state: RuntimeValue,
-    //    module_instance: ModuleRef,
-    //    library_instance: ModuleRef,
-    //    mem: ExternVal,
-    //    alloc_string: ExternVal,
-    //    get_string_buf: ExternVal,
-    //    make_string: ExternVal,
-    rtl_module_instance: Rc<wasmer_runtime::Instance>,
-    fm_import_object: wasmer_runtime::ImportObject,
-    formula_module_instance: wasmer_runtime::Instance,
-    fm_init_res: i32,
-    context: &'b Context<'a>,
+    pub fn set_f64(&mut self, fval: f64) -> Result<()> {
+        self.check_type(Type::F64)?;
+        self.ct.evaluator.set_f64(self.idx, fval)
+    }
+
+    /// Sets the value of the references variable to ```bval```. Both
+    /// backends store booleans assigned to untyped variables as the
+    /// integers 0 and 1, which act as booleans when the variable is
+    /// used bare in boolean position and otherwise compare like
+    /// integers. Comparing an untyped variable with a boolean fails to
+    /// compile; declare the variable as ```Type::Bool``` to do that.
+    pub fn set_bool(&mut self, bval: bool) -> Result<()> {
+        match self.ty {
+            None => self.ct.evaluator.set_i64(self.idx, bval as i64),
+            Some(_) => {
+                self.check_type(Type::Bool)?;
+                self.ct.evaluator.set_bool(self.idx, bval)
+            }
+        }
+    }
+
+    /// Sets the value of the references variable to ```bytes```
+    pub fn set_bytes(&mut self, bytes: &[u8]) -> Result<()> {
+        self.check_type(Type::Bytes)?;
+        self.ct.evaluator.set_bytes(self.idx, bytes)
+    }
+
+    /// Sets the references variable to null, i.e. no value. This is
+    /// also the value of variables that were never set.
+    pub fn set_null(&mut self) -> Result<()> {
+        self.ct.evaluator.set_null(self.idx)
+    }
+
+    /// Sets the value of the references variable to ```time```,
+    /// stored as nanoseconds since the unix epoch
+    pub fn set_timestamp(&mut self, time: SystemTime) -> Result<()> {
+        self.check_type(Type::Timestamp)?;
+        self.ct.evaluator.set_i64(self.idx, timestamp_nanos(time)?)
+    }
+
+    fn check_type(&self, ty: Type) -> Result<()> {
+        check_type(self.ty, ty)
+    }
+}
+
+/// A typed variable of a specific instance of a formula, obtained
+/// with ```InstantiatedTerm::get_typed_variable```. Unlike
+/// ```Variable```, it only offers the setter matching the variable's
+/// declared type, so assigning a value of the wrong type does not
+/// compile.
+pub struct TypedVariable<'b, T> {
+    var: Variable<'b>,
+    marker: PhantomData<fn(T)>,
+}
+
+impl<'b, T: VarValue> TypedVariable<'b, T> {
+    /// Sets the value of the references variable to ```value```
+    pub fn set(&mut self, value: T) -> Result<()> {
+        value.assign(&mut self.var)
+    }
+
+    /// Sets the references variable to null, i.e. no value. This is
+    /// also the value of variables that were never set.
+    pub fn set_null(&mut self) -> Result<()> {
+        self.var.set_null()
+    }
+}
+
+/// Rust types that can be assigned to a variable of the matching
+/// declared ```Type```.
+pub trait VarValue {
+    /// The declared type of variables this type can be assigned to
+    const TYPE: Type;
+
+    #[doc(hidden)]
+    fn assign(self, var: &mut Variable) -> Result<()>;
+}
+
+impl VarValue for f64 {
+    const TYPE: Type = Type::F64;
+
+    fn assign(self, var: &mut Variable) -> Result<()> {
+        var.set_f64(self)
+    }
+}
+
+impl VarValue for i64 {
+    const TYPE: Type = Type::I64;
+
+    fn assign(self, var: &mut Variable) -> Result<()> {
+        var.set_i64(self)
+    }
+}
+
+impl VarValue for bool {
+    const TYPE: Type = Type::Bool;
+
+    fn assign(self, var: &mut Variable) -> Result<()> {
+        var.set_bool(self)
+    }
+}
+
+impl<'v> VarValue for &'v str {
+    const TYPE: Type = Type::String;
+
+    fn assign(self, var: &mut Variable) -> Result<()> {
+        var.set_string(self)
+    }
+}
+
+impl<'v> VarValue for &'v [u8] {
+    const TYPE: Type = Type::Bytes;
+
+    fn assign(self, var: &mut Variable) -> Result<()> {
+        var.set_bytes(self)
+    }
+}
+
+impl VarValue for SystemTime {
+    const TYPE: Type = Type::Timestamp;
+
+    fn assign(self, var: &mut Variable) -> Result<()> {
+        var.set_timestamp(self)
+    }
+}
+
+/// Check that a value of type ```given``` may be assigned to a
+/// variable declared with type ```declared```. Untyped variables
+/// accept values of any type.
+fn check_type(declared: Option<Type>, given: Type) -> Result<()> {
+    match declared {
+        Some(declared) if declared != given => Err(Error::TypeMismatch(format!(
+            "variable is declared as {:?}, cannot assign {:?}",
+            declared, given
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Nanoseconds between the unix epoch and ```time```, negative for
+/// times before the epoch.
+fn timestamp_nanos(time: SystemTime) -> Result<i64> {
+    let out_of_range = |_| Error::TypeMismatch(format!("timestamp {:?} is out of range", time));
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(after) => i64::try_from(after.as_nanos()).map_err(out_of_range),
+        Err(before) => i64::try_from(before.duration().as_nanos())
+            .map(|nanos| -nanos)
+            .map_err(out_of_range),
+    }
+}
+
+/// Evaluator is implemented by the backends that execute instantiated
+/// formulas. Variables are addressed by the slot assigned to them by
+/// the context.
+pub trait Evaluator {
+    /// Sets the variable in ```slot``` to ```intval```
+    fn set_i64(&self, slot: i64, intval: i64) -> Result<()>;
+
+    /// Sets the variable in ```slot``` to ```fval```
+    fn set_f64(&self, slot: i64, fval: f64) -> Result<()>;
+
+    /// Sets the variable in ```slot``` to ```string```
+    fn set_string(&self, slot: i64, string: &str) -> Result<()>;
+
+    /// Sets the variable in ```slot``` to ```bval```
+    fn set_bool(&self, slot: i64, bval: bool) -> Result<()>;
+
+    /// Sets the variable in ```slot``` to ```bytes```
+    fn set_bytes(&self, slot: i64, bytes: &[u8]) -> Result<()>;
+
+    /// Sets the variable in ```slot``` to null
+    fn set_null(&self, slot: i64) -> Result<()>;
+
+    /// Evaluate the formula with the current values of the variables;
+    /// None if the result is unknown
+    fn run(&self) -> Result<Option<bool>>;
+
+    /// Evaluate the formula for ```rows``` rows, taking the variables'
+    /// values from ```columns```, along with the variables' slots and
+    /// declared types.
+    fn run_batch(
+        &self,
+        columns: &[(i64, Option<Type>, &Column)],
+        rows: usize,
+    ) -> Result<Vec<bool>> {
+        let mut results = Vec::with_capacity(rows);
+        for row in 0..rows {
+            for (slot, ty, column) in columns {
+                let (values, nulls) = column.split();
+                if nulls.map(|nulls| nulls[row]).unwrap_or(false) {
+                    self.set_null(*slot)?;
+                    continue;
+                }
+                match values {
+                    Column::F64(values) => self.set_f64(*slot, values[row])?,
+                    Column::I64(values) => self.set_i64(*slot, values[row])?,
+                    Column::String(values) => self.set_string(*slot, values[row])?,
+                    // Like Variable::set_bool
+                    Column::Bool(values) if ty.is_none() => {
+                        self.set_i64(*slot, values[row] as i64)?
+                    }
+                    Column::Bool(values) => self.set_bool(*slot, values[row])?,
+                    Column::Bytes(values) => self.set_bytes(*slot, values[row])?,
+                    Column::Timestamp(_) => self.set_i64(*slot, values.word(row)?)?,
+                    Column::Nullable(_, _) => return Err(nested_nulls()),
+                }
+            }
+            results.push(self.run()? == Some(true));
+        }
+        Ok(results)
+    }
+}
+
+/// Backend used by a context to evaluate its formulas
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Backend {
+    /// Compile formulas to webassembly and run them with wasmer.
+    /// Compiling and instantiating formulas is comparatively slow,
+    /// evaluating them is fast.
+    Wasm,
+    /// Evaluate formulas by walking their AST. There is no startup
+    /// cost, which makes this backend suitable for formulas that are
+    /// only evaluated a few times.
+    Interpreter,
+}
+
+/// Static type of a variable declared with ```define_var_typed```.
+///
+/// Formulas using typed variables are type checked when compiled.
+/// Numeric, boolean and timestamp variables are moreover stored
+/// natively rather than as runtime values, which lets comparisons
+/// between them and literals compile to plain wasm instructions.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Type {
+    F64,
+    I64,
+    Bool,
+    String,
+    /// Arbitrary bytes, comparable with strings
+    Bytes,
+    /// A point in time, set from a ```SystemTime```. Timestamps can
+    /// only be compared with other timestamps.
+    Timestamp,
+}
+
+impl Type {
+    /// The wasm type a variable of this type is stored as, None if
+    /// it is stored as a runtime value
+    fn storage(self) -> Option<Type> {
+        match self {
+            Type::F64 => Some(Type::F64),
+            Type::I64 | Type::Bool | Type::Timestamp => Some(Type::I64),
+            Type::String | Type::Bytes => None,
+        }
+    }
+
+    fn is_numeric(self) -> bool {
+        self == Type::F64 || self == Type::I64
+    }
+
+    fn is_text(self) -> bool {
+        self == Type::String || self == Type::Bytes
+    }
+}
+
+/// Static type of a term, as determined by ```Context::int_type_of```
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum StaticType {
+    Known(Type),
+    Regex,
+    /// An untyped variable, whose type is only known at runtime
+    Dynamic,
+}
+
+/// CompiledTerm represents a compiled formula. It does not borrow
+/// the context or the term it was compiled from, so it can be kept
+/// e.g. in a map of rules. It is ```Send``` and ```Sync```: a formula
+/// compiled once can be instantiated on any thread.
+pub struct CompiledTerm {
+    backend: CompiledBackend,
+    variables: Arc<VariableSlots>,
+}
+
+enum CompiledBackend {
+    Wasm(Arc<CompiledWasm>),
+    Interpreter(Arc<Program>),
+}
+
+/// The slots and declared types of the variables a formula was
+/// compiled for, shared by the formula's instances
+struct VariableSlots {
+    slots: HashMap<String, i64>,
+    types: HashMap<i64, Type>,
+}
+
+struct CompiledWasm {
+    /// The runtime library, instantiated anew for every instance of
+    /// the formula
+    rtl_module: wasmer_runtime::Module,
+    formula_module: wasmer_runtime::Module,
+    wasm: Vec<u8>,
+    /// Type and native storage cell of natively stored typed
+    /// variables, by slot
+    native: HashMap<i64, (Type, u32)>,
+    /// Slots of the variables, which are null until they are set
+    null_slots: Vec<i64>,
+    /// Number of slots that have a null flag
+    slot_count: usize,
+    /// Slots of the untyped variables whose value cells are read by
+    /// orderings and arithmetic, see GLOBAL_CELLS
+    dynamic_slots: Vec<i64>,
 }
 
 /// InstantiatedTerm represents a loaded (i.e., AOT compiled)
-/// formula with associated variables
-pub struct InstantiatedTerm<'b, 'a: 'b> {
-    ct: &'b CompiledTerm<'b, 'a>,
-    make_i64_call: wasmer_runtime::Func<'b, (i32, i64, i64), i64>,
-    make_f64_call: wasmer_runtime::Func<'b, (i32, i64, f64), i64>,
-    alloc_string_call: wasmer_runtime::Func<'b, (i32), (i32)>,
-    get_string_buf_call: wasmer_runtime::Func<'b, (i32), (i32)>,
-    make_string_call: wasmer_runtime::Func<'b, (i32, i64, i32, i32), (i64)>,
-    eval_call: wasmer_runtime::Func<'b, i32, i32>,
-    memory: &'b wasmer_runtime::Memory,
+/// formula with associated variables. Like ```CompiledTerm```, it
+/// owns its data. Every instance has its own runtime library
+/// instance and thus its own variables; the instances of a formula
+/// only share its compiled modules.
+///
+/// InstantiatedTerm is not ```Send```, as wasmer instances are bound
+/// to the thread that created them; instantiate the compiled formula
+/// on every thread that evaluates it instead.
+pub struct InstantiatedTerm {
+    variables: Arc<VariableSlots>,
+    evaluator: Box<dyn Evaluator>,
+}
+
+/// The wasm backend's Evaluator. The exported functions are looked
+/// up on every call, as wasmer's function handles borrow the
+/// instance.
+struct WasmEvaluator {
+    compiled: Arc<CompiledWasm>,
+    rtl_instance: Rc<wasmer_runtime::Instance>,
+    formula_instance: wasmer_runtime::Instance,
+    /// The runtime state created by the formula's load function
+    state: i32,
+    memory: wasmer_runtime::Memory,
+    batch_buffer: RefCell<Option<(usize, usize)>>,
+    native_base: usize,
+    /// Address of the null flags, one byte per slot following the
+    /// storage of typed variables
+    null_flags: usize,
+    /// Address of the value cells, see GLOBAL_CELLS
+    cells: usize,
+    /// Buffers the value cells of dynamic slots point to for texts, by
+    /// slot: their address and capacity
+    shadows: RefCell<HashMap<i64, (usize, usize)>>,
+}
+
+/// A column of values for one variable, used for batch evaluation
+/// with ```InstantiatedTerm::run_batch```.
+pub enum Column<'c> {
+    F64(&'c [f64]),
+    I64(&'c [i64]),
+    String(&'c [&'c str]),
+    Bool(&'c [bool]),
+    Bytes(&'c [&'c [u8]]),
+    Timestamp(&'c [SystemTime]),
+    /// The values of the inner column, except in the rows where the
+    /// mask is true, in which the variable is null
+    Nullable(&'c Column<'c>, &'c [bool]),
+}
+
+impl<'c> Column<'c> {
+    fn len(&self) -> usize {
+        match self {
+            Column::F64(values) => values.len(),
+            Column::I64(values) => values.len(),
+            Column::String(values) => values.len(),
+            Column::Bool(values) => values.len(),
+            Column::Bytes(values) => values.len(),
+            Column::Timestamp(values) => values.len(),
+            Column::Nullable(values, _) => values.len(),
+        }
+    }
+
+    /// The column holding the values, along with the null mask
+    fn split(&self) -> (&Column<'c>, Option<&'c [bool]>) {
+        match self {
+            Column::Nullable(values, nulls) => (values, Some(nulls)),
+            _ => (self, None),
+        }
+    }
+
+    /// The text in ```row``` of a string or bytes column
+    fn text(&self, row: usize) -> Option<&'c [u8]> {
+        match self {
+            Column::String(values) => Some(values[row].as_bytes()),
+            Column::Bytes(values) => Some(values[row]),
+            _ => None,
+        }
+    }
+
+    /// The value in ```row``` of a column of numbers, booleans or
+    /// timestamps as stored in 8 bytes: f64 as their bits, booleans as
+    /// 0 or 1 and timestamps as nanoseconds since the unix epoch
+    fn word(&self, row: usize) -> Result<i64> {
+        match self {
+            Column::F64(values) => Ok(values[row].to_bits() as i64),
+            Column::I64(values) => Ok(values[row]),
+            Column::Bool(values) => Ok(values[row] as i64),
+            Column::Timestamp(values) => timestamp_nanos(values[row]),
+            _ => Err(Error::InvalidInput(
+                "not a column of numbers -- bug!".to_string(),
+            )),
+        }
+    }
 }
 
 fn print_str(ctx: &mut wasmer_runtime::Ctx, ptr: u32, len: u32) {
@@ -131,6 +564,523 @@ fn print_str(ctx: &mut wasmer_runtime::Ctx, ptr: u32, len: u32) {
     println!("WASM: {}", s);
 }
 
+/// Build the body of the function ```compare_text(a, b) -> i32```,
+/// which compares the texts ```a``` and ```b```, each given as its
+/// address and length like in a value cell, bytewise. Returns -1, 0
+/// or 1 if ```a``` is less than, equal to or greater than ```b```.
+fn build_compare_text() -> Vec<Instruction> {
+    use parity_wasm::elements::Instruction::*;
+    let (a, b) = (0, 1);
+    let (pa, la, pb, lb, i, ca, cb) = (2, 3, 4, 5, 6, 7, 8);
+    vec![
+        GetLocal(a),
+        I32WrapI64,
+        SetLocal(pa),
+        GetLocal(a),
+        I64Const(32),
+        I64ShrU,
+        I32WrapI64,
+        SetLocal(la),
+        GetLocal(b),
+        I32WrapI64,
+        SetLocal(pb),
+        GetLocal(b),
+        I64Const(32),
+        I64ShrU,
+        I32WrapI64,
+        SetLocal(lb),
+        I32Const(0),
+        SetLocal(i),
+        Block(BlockType::NoResult),
+        Loop(BlockType::NoResult),
+        GetLocal(i),
+        GetLocal(la),
+        I32GeU,
+        GetLocal(i),
+        GetLocal(lb),
+        I32GeU,
+        I32Or,
+        BrIf(1),
+        GetLocal(pa),
+        GetLocal(i),
+        I32Add,
+        I32Load8U(0, 0),
+        SetLocal(ca),
+        GetLocal(pb),
+        GetLocal(i),
+        I32Add,
+        I32Load8U(0, 0),
+        SetLocal(cb),
+        // The first differing byte decides
+        GetLocal(ca),
+        GetLocal(cb),
+        I32Ne,
+        If(BlockType::NoResult),
+        GetLocal(ca),
+        GetLocal(cb),
+        I32GtU,
+        GetLocal(ca),
+        GetLocal(cb),
+        I32LtU,
+        I32Sub,
+        Return,
+        End,
+        GetLocal(i),
+        I32Const(1),
+        I32Add,
+        SetLocal(i),
+        Br(0),
+        End,
+        End,
+        // Otherwise the shorter text is a prefix of the longer one
+        GetLocal(la),
+        GetLocal(lb),
+        I32GtU,
+        GetLocal(la),
+        GetLocal(lb),
+        I32LtU,
+        I32Sub,
+        End,
+    ]
+}
+
+/// Byte offset of ```fragment``` within ```source```, if ```fragment```
+/// is a slice of ```source```.
+fn offset_in(source: &str, fragment: &str) -> Option<usize> {
+    let start = source.as_ptr() as usize;
+    let pos = fragment.as_ptr() as usize;
+    if pos >= start && pos + fragment.len() <= start + source.len() {
+        Some(pos - start)
+    } else {
+        None
+    }
+}
+
+/// Build the body of the exported function
+/// ```eval_batch(state, descs, num_cols, rows, out) -> i32```.
+///
+/// For every row, each of the ```num_cols``` column descriptors at
+/// ```descs``` is used to load the row's value into the descriptor's
+/// slot, then the formula is evaluated and its result stored as a
+/// bit into the bitmap at ```out```, which must be zeroed. String
+/// columns consist of (pointer, length) pairs of i32. Except for
+/// typed variables, the value is also stored in the slot's value
+/// cell, see GLOBAL_CELLS. If the column has a null mask, the slot's
+/// null flag is set from it.
+fn build_eval_batch() -> Vec<Instruction> {
+    use parity_wasm::elements::Instruction::*;
+    let (state, descs, num_cols, rows, out) = (0, 1, 2, 3, 4);
+    let (row, col, desc, kind, res, entry, handle, dst, k) = (5, 6, 7, 8, 9, 10, 11, 12, 13);
+
+    // Address of the current row's value within the column at desc
+    let row_addr = |width: i32| {
+        vec![
+            GetLocal(desc),
+            I32Load(2, 12),
+            GetLocal(row),
+            I32Const(width),
+            I32Mul,
+            I32Add,
+        ]
+    };
+
+    let mut code = vec![
+        I32Const(0),
+        SetLocal(row),
+        Block(BlockType::NoResult),
+        Loop(BlockType::NoResult),
+    ];
+    code.extend(vec![GetLocal(row), GetLocal(rows), I32GeU, BrIf(1)]);
+
+    // Load this row's values into the variables' slots
+    code.extend(vec![
+        I32Const(0),
+        SetLocal(col),
+        Block(BlockType::NoResult),
+        Loop(BlockType::NoResult),
+        GetLocal(col),
+        GetLocal(num_cols),
+        I32GeU,
+        BrIf(1),
+        GetLocal(descs),
+        GetLocal(col),
+        I32Const(COLUMN_DESC_SIZE as i32),
+        I32Mul,
+        I32Add,
+        SetLocal(desc),
+        GetLocal(desc),
+        I32Load(2, 8),
+        SetLocal(kind),
+    ]);
+
+    code.extend(vec![
+        GetLocal(kind),
+        I32Const(COLUMN_F64),
+        I32Eq,
+        If(BlockType::NoResult),
+    ]);
+    code.extend(vec![GetLocal(state), GetLocal(desc), I64Load(3, 0)]);
+    code.extend(row_addr(8));
+    code.extend(vec![F64Load(3, 0), Call(FUN_MAKE_F64), Drop, End]);
+
+    code.extend(vec![
+        GetLocal(kind),
+        I32Const(COLUMN_I64),
+        I32Eq,
+        If(BlockType::NoResult),
+    ]);
+    code.extend(vec![GetLocal(state), GetLocal(desc), I64Load(3, 0)]);
+    code.extend(row_addr(8));
+    code.extend(vec![I64Load(3, 0), Call(FUN_MAKE_I64), Drop, End]);
+
+    code.extend(vec![
+        GetLocal(kind),
+        I32Const(COLUMN_NATIVE),
+        I32Eq,
+        If(BlockType::NoResult),
+    ]);
+    code.extend(vec![GetLocal(desc), I64Load(3, 0), I32WrapI64]);
+    code.extend(row_addr(8));
+    code.extend(vec![I64Load(3, 0), I64Store(3, 0), End]);
+
+    code.extend(vec![
+        GetLocal(kind),
+        I32Const(COLUMN_STRING),
+        I32Eq,
+        If(BlockType::NoResult),
+    ]);
+    code.extend(row_addr(8));
+    code.extend(vec![
+        SetLocal(entry),
+        // Copy the string's bytes to a fresh string buffer
+        GetLocal(entry),
+        I32Load(2, 4),
+        Call(FUN_ALLOC_STRING),
+        TeeLocal(handle),
+        Call(FUN_GET_STRING_BUF),
+        SetLocal(dst),
+        I32Const(0),
+        SetLocal(k),
+        Block(BlockType::NoResult),
+        Loop(BlockType::NoResult),
+        GetLocal(k),
+        GetLocal(entry),
+        I32Load(2, 4),
+        I32GeU,
+        BrIf(1),
+        GetLocal(dst),
+        GetLocal(k),
+        I32Add,
+        GetLocal(entry),
+        I32Load(2, 0),
+        GetLocal(k),
+        I32Add,
+        I32Load8U(0, 0),
+        I32Store8(0, 0),
+        GetLocal(k),
+        I32Const(1),
+        I32Add,
+        SetLocal(k),
+        Br(0),
+        End,
+        End,
+        GetLocal(state),
+        GetLocal(desc),
+        I64Load(3, 0),
+        GetLocal(handle),
+        GetLocal(entry),
+        I32Load(2, 4),
+        Call(FUN_MAKE_STRING),
+        Drop,
+        End,
+    ]);
+
+    // The value cell holds the row's 8 bytes, the address and length
+    // of a string
+    code.extend(vec![
+        GetLocal(kind),
+        I32Const(COLUMN_NATIVE),
+        I32Ne,
+        If(BlockType::NoResult),
+        GetLocal(desc),
+        I32Load(2, 16),
+        GetLocal(desc),
+        I32Load(2, 20),
+        I32Store(2, 0),
+        GetLocal(desc),
+        I32Load(2, 16),
+    ]);
+    code.extend(row_addr(8));
+    code.extend(vec![I64Load(3, 0), I64Store(3, 8), End]);
+
+    // Columns with a null mask set the slot's null flag to the row's
+    // byte of the mask
+    code.extend(vec![
+        GetLocal(desc),
+        I32Load(2, 24),
+        If(BlockType::NoResult),
+        GetLocal(desc),
+        I32Load(2, 28),
+        GetLocal(desc),
+        I32Load(2, 24),
+        GetLocal(row),
+        I32Add,
+        I32Load8U(0, 0),
+        I32Store8(0, 0),
+        End,
+    ]);
+
+    code.extend(vec![
+        GetLocal(col),
+        I32Const(1),
+        I32Add,
+        SetLocal(col),
+        Br(0),
+        End,
+        End,
+    ]);
+
+    // out[row >> 3] |= (eval(state) == TRUTH_TRUE) << (row & 7)
+    code.extend(vec![
+        GetLocal(state),
+        Call(FUN_EVAL),
+        SetLocal(res),
+        GetLocal(out),
+        GetLocal(row),
+        I32Const(3),
+        I32ShrU,
+        I32Add,
+        GetLocal(out),
+        GetLocal(row),
+        I32Const(3),
+        I32ShrU,
+        I32Add,
+        I32Load8U(0, 0),
+        GetLocal(res),
+        I32Const(TRUTH_TRUE),
+        I32Eq,
+        GetLocal(row),
+        I32Const(7),
+        I32And,
+        I32Shl,
+        I32Or,
+        I32Store8(0, 0),
+        GetLocal(row),
+        I32Const(1),
+        I32Add,
+        SetLocal(row),
+        Br(0),
+        End,
+        End,
+        I32Const(0),
+        End,
+    ]);
+    code
+}
+
+/// Check that the string or regex literal ```bytes``` is not longer
+/// than the runtime library's i32 lengths allow
+fn check_literal_len(bytes: &[u8]) -> Result<()> {
+    if bytes.len() > i32::max_value() as usize {
+        return Err(Error::InvalidInput(format!(
+            "literal of {} bytes is too long",
+            bytes.len()
+        )));
+    }
+    Ok(())
+}
+
+/// Offset of the value cells from the storage of ```native``` typed
+/// variables, which is followed by the null flags of ```slots``` slots
+fn cells_offset(native: usize, slots: usize) -> usize {
+    align8(8 * native + slots)
+}
+
+fn nested_nulls() -> Error {
+    Error::InvalidInput("a nullable column cannot hold another nullable column".to_string())
+}
+
+/// The VALUE_ tag of the values in a column of ```kind```
+fn column_value(kind: i32) -> i32 {
+    if kind == COLUMN_F64 {
+        VALUE_F64
+    } else if kind == COLUMN_STRING {
+        VALUE_TEXT
+    } else {
+        VALUE_I64
+    }
+}
+
+fn column_kind(column: &Column) -> i32 {
+    match column {
+        Column::F64(_) => COLUMN_F64,
+        Column::I64(_) | Column::Bool(_) | Column::Timestamp(_) => COLUMN_I64,
+        Column::String(_) | Column::Bytes(_) => COLUMN_STRING,
+        Column::Nullable(values, _) => column_kind(values),
+    }
+}
+
+/// Type of the values in ```column```
+fn column_type(column: &Column) -> Type {
+    match column {
+        Column::F64(_) => Type::F64,
+        Column::I64(_) => Type::I64,
+        Column::String(_) => Type::String,
+        Column::Bool(_) => Type::Bool,
+        Column::Bytes(_) => Type::Bytes,
+        Column::Timestamp(_) => Type::Timestamp,
+        Column::Nullable(values, _) => column_type(values),
+    }
+}
+
+/// Human readable name of a static type, for error messages
+fn describe(ty: StaticType) -> String {
+    match ty {
+        StaticType::Known(ty) => format!("{:?}", ty),
+        StaticType::Regex => "Regex".to_string(),
+        StaticType::Dynamic => "untyped".to_string(),
+    }
+}
+
+/// Whether ```t``` evaluates to true, false or unknown rather than
+/// to a value of another type
+fn is_truth_valued(t: &Term) -> bool {
+    match t {
+        Term::Bool(_)
+        | Term::Not(_)
+        | Term::And(_, _)
+        | Term::Or(_, _)
+        | Term::Eq(_, _)
+        | Term::Lt(_, _)
+        | Term::Le(_, _)
+        | Term::Gt(_, _)
+        | Term::Ge(_, _)
+        | Term::Matches(_, _)
+        | Term::In(_, _)
+        | Term::IsNull(_) => true,
+        _ => false,
+    }
+}
+
+/// Type of the result of arithmetic on operands stored as ```ty1```
+/// and ```ty2```
+fn promote(ty1: Type, ty2: Type) -> Type {
+    if ty1 == Type::F64 || ty2 == Type::F64 {
+        Type::F64
+    } else {
+        Type::I64
+    }
+}
+
+fn not_native() -> Error {
+    Error::UnsupportedOperator("not a native operand -- bug!".to_string())
+}
+
+/// The value of a numeric or boolean literal as f64
+fn literal_f64(t: &Term) -> f64 {
+    match t {
+        Term::Bool(bval) => *bval as i64 as f64,
+        Term::Int(intval) => *intval as f64,
+        Term::Float(fval) => *fval,
+        _ => f64::NAN,
+    }
+}
+
+/// The order of two numeric or two string literals, as compared by
+/// the runtime
+fn literal_order(a: &Term, b: &Term) -> Ordering {
+    match (a, b) {
+        (Term::Int(a), Term::Int(b)) => a.cmp(b),
+        (Term::String(a), Term::String(b)) => a.as_bytes().cmp(b.as_bytes()),
+        _ => literal_f64(a)
+            .partial_cmp(&literal_f64(b))
+            .unwrap_or(Ordering::Equal),
+    }
+}
+
+/// The numeric or boolean literals ```keys``` as sorted i64 literals
+fn int_keys<'a>(keys: &[Term]) -> Vec<Term<'a>> {
+    let mut values: Vec<i64> = keys
+        .iter()
+        .map(|key| match key {
+            Term::Bool(bval) => *bval as i64,
+            Term::Int(intval) => *intval,
+            _ => 0,
+        })
+        .collect();
+    values.sort_unstable();
+    values.dedup();
+    values.into_iter().map(Term::Int).collect()
+}
+
+/// The numeric or boolean literals ```keys``` as sorted f64 literals
+fn float_keys<'a>(keys: &[Term]) -> Vec<Term<'a>> {
+    let mut values: Vec<f64> = keys.iter().map(literal_f64).collect();
+    values.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+    values.dedup();
+    values.into_iter().map(Term::Float).collect()
+}
+
+/// Whether values of type ```ty1``` and ```ty2``` can be compared for
+/// equality
+fn comparable(ty1: StaticType, ty2: StaticType) -> bool {
+    match (ty1, ty2) {
+        // Regexes are matched with =~
+        (StaticType::Regex, _) | (_, StaticType::Regex) => false,
+        // Untyped variables hold booleans as integers, so they would
+        // never equal a boolean
+        (StaticType::Dynamic, StaticType::Known(Type::Bool))
+        | (StaticType::Known(Type::Bool), StaticType::Dynamic) => false,
+        (StaticType::Dynamic, _) | (_, StaticType::Dynamic) => true,
+        (StaticType::Known(ty1), StaticType::Known(ty2)) => {
+            ty1 == ty2 || (ty1.is_numeric() && ty2.is_numeric()) || (ty1.is_text() && ty2.is_text())
+        }
+    }
+}
+
+fn type_mismatch(operator: &str, ty1: StaticType, ty2: StaticType) -> Error {
+    let hint = match (ty1, ty2) {
+        (StaticType::Dynamic, StaticType::Known(Type::Bool))
+        | (StaticType::Known(Type::Bool), StaticType::Dynamic) => {
+            "; untyped variables hold booleans as the integers 0 and 1, \
+             declare the variable as Bool to compare it with booleans"
+        }
+        _ => "",
+    };
+    Error::TypeMismatch(format!(
+        "cannot apply {} to {} and {}{}",
+        operator,
+        describe(ty1),
+        describe(ty2),
+        hint
+    ))
+}
+
+/// Round ```n``` up to the next multiple of eight.
+fn align8(n: usize) -> usize {
+    (n + 7) & !7
+}
+
+/// 64 bit FNV-1a hash. Used instead of std's hashers because its
+/// output must be stable across rust releases.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, b| {
+        (hash ^ u64::from(*b)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+fn trap<E: std::fmt::Display>(what: &'static str) -> impl Fn(E) -> Error {
+    move |e| Error::Trap(format!("{}: {}", what, e))
+}
+
+fn instantiate_error<E: std::fmt::Display>(what: &'static str) -> impl Fn(E) -> Error {
+    move |e| Error::WasmInstantiate(format!("{}: {}", what, e))
+}
+
+fn compile_error<E: std::fmt::Display>(what: &'static str) -> impl Fn(E) -> Error {
+    move |e| Error::WasmCompile(format!("{}: {}", what, e))
+}
+
 impl<'a> Context<'a> {
     /// Create a new context.
     ///
@@ -144,32 +1094,63 @@ impl<'a> Context<'a> {
     /// All formulas share the names of the variables declared
     /// per context. This does not mean that the values of variables
     /// are shared between formulas.
-    pub fn new() -> Context<'a> {
+    pub fn new() -> Result<Context<'a>> {
+        Context::with_backend(Backend::Wasm)
+    }
+
+    /// Create a new context that evaluates its formulas with the
+    /// given backend. Only the wasm backend needs to compile the
+    /// runtime library, so creating an interpreter context is cheap.
+    pub fn with_backend(backend: Backend) -> Result<Context<'a>> {
         let variables = HashMap::new();
-        let instructions = Vec::new();
-        let idx_state = None;
-        let idx_result = None;
-        let idx_string = None;
-        let idx_mem_buf_ptr = None;
-        let idx_var_result = None;
+        let types = HashMap::new();
+        let native = HashMap::new();
         let locals = 1; /* Need to start counting at 1! */
-        let reserved_slots = 1000;
+        let lenient = false;
+        let dump_dir = None;
 
-        let lib_wasm_rtl =
-            include_bytes!("../wormrtl/target/wasm32-unknown-unknown/release/wormrtl.wasm");
-        let rtl_module = wasmer_runtime::compile(lib_wasm_rtl).expect("wormrtl.wasm module");
+        let rtl_module = match backend {
+            Backend::Wasm => {
+                let lib_wasm_rtl =
+                    include_bytes!("../wormrtl/target/wasm32-unknown-unknown/release/wormrtl.wasm");
+                Some(wasmer_runtime::compile(lib_wasm_rtl).map_err(compile_error("wormrtl.wasm"))?)
+            }
+            Backend::Interpreter => None,
+        };
 
-        Context {
+        Ok(Context {
             rtl_module,
             variables,
-            instructions,
-            idx_state,
-            idx_result,
-            idx_string,
-            idx_mem_buf_ptr,
-            idx_var_result,
+            types,
+            native,
             locals,
-            reserved_slots,
+            lenient,
+            dump_dir,
+        })
+    }
+
+    /// Declare a variable with a static type. Formulas using the
+    /// variable are type checked on compilation, e.g. ```name > 3```
+    /// is rejected if ```name``` is a string. Comparisons between
+    /// natively stored typed variables and literals are compiled to
+    /// wasm instructions instead of calls into the runtime library.
+    /// Only the setter matching the declared type may be used.
+    pub fn define_var_typed(&mut self, var_name: &'a str, ty: Type) {
+        let var_num = self.locals;
+        self.locals += 1;
+        self.variables.insert(var_name, var_num);
+        self.types.insert(var_num, ty);
+        if ty.storage().is_some() {
+            let cell = self.native.len() as u32;
+            self.native.insert(var_num, (ty, cell));
+        }
+    }
+
+    /// The backend used to evaluate formulas compiled by this context
+    pub fn backend(&self) -> Backend {
+        match self.rtl_module {
+            Some(_) => Backend::Wasm,
+            None => Backend::Interpreter,
         }
     }
 
@@ -177,14 +1158,473 @@ impl<'a> Context<'a> {
     /// context need to be previously declared using the define_var
     /// function. Since types are associated at runtime, there
     /// is no need to declare the type of the variable here.
+    ///
+    /// The name may be a path into a structured record such as
+    /// ```http.status``` or ```tags[0]```, spelled as in the formula;
+    /// see ```InstantiatedTerm::set_record```.
     pub fn define_var<'b>(&'b mut self, var_name: &'a str) {
         let var_num = self.locals;
         self.locals += 1;
         self.variables.insert(var_name, var_num);
     }
 
-    fn int_build_loader<'d>(&mut self, t: &Term) -> Term<'d> {
+    /// By default, compiling a formula that references a variable
+    /// not declared with define_var fails. In lenient mode, such
+    /// variables are instead treated as null, i.e. as a slot that is
+    /// never assigned a value.
+    pub fn set_lenient(&mut self, lenient: bool) {
+        self.lenient = lenient;
+    }
+
+    /// Debugging aid: when set, every formula module compiled by this
+    /// context is written to ```dir```. The file name is derived from
+    /// the module's contents, so compiling the same formula again
+    /// overwrites the same file. Pass ```None``` to disable dumping,
+    /// which is the default.
+    pub fn set_dump_dir<P: AsRef<Path>>(&mut self, dir: Option<P>) {
+        self.dump_dir = dir.map(|d| d.as_ref().to_path_buf());
+    }
+
+    /// Fill in the offsets of ```unknown``` identifiers that are not
+    /// slices of ```source```, e.g. of terms detached from it with
+    /// ```into_owned```, by parsing ```source``` again. Nothing is
+    /// filled in unless it yields the same unknown identifiers.
+    fn int_locate_unknown(&self, source: &str, unknown: &mut [UnknownIdentifier]) {
+        if unknown.iter().all(|ident| ident.offset.is_some()) {
+            return;
+        }
+        let reparsed = match parse_formula(source) {
+            Ok(reparsed) => reparsed,
+            Err(_) => return,
+        };
+        let mut located = Vec::new();
+        self.int_find_unknown(&reparsed, Some(source), &mut located);
+        let same = located.len() == unknown.len()
+            && located
+                .iter()
+                .zip(unknown.iter())
+                .all(|(l, u)| l.name == u.name);
+        if same {
+            for (ident, located) in unknown.iter_mut().zip(located) {
+                ident.offset = located.offset;
+            }
+        }
+    }
+
+    fn int_find_unknown(
+        &self,
+        t: &Term,
+        source: Option<&str>,
+        unknown: &mut Vec<UnknownIdentifier>,
+    ) {
+        match t {
+            Term::Variable(var_name) => {
+                if !self.variables.contains_key(&**var_name) {
+                    unknown.push(UnknownIdentifier {
+                        name: var_name.to_string(),
+                        offset: source.and_then(|s| offset_in(s, var_name)),
+                    });
+                }
+            }
+            Term::Not(inner) | Term::Neg(inner) | Term::IsNull(inner) => {
+                self.int_find_unknown(inner, source, unknown)
+            }
+            Term::In(subject, list) => {
+                self.int_find_unknown(subject, source, unknown);
+                for element in list {
+                    self.int_find_unknown(element, source, unknown);
+                }
+            }
+            Term::Eq(t1, t2)
+            | Term::Or(t1, t2)
+            | Term::And(t1, t2)
+            | Term::Lt(t1, t2)
+            | Term::Le(t1, t2)
+            | Term::Gt(t1, t2)
+            | Term::Ge(t1, t2)
+            | Term::Matches(t1, t2)
+            | Term::Add(t1, t2)
+            | Term::Sub(t1, t2)
+            | Term::Mul(t1, t2)
+            | Term::Div(t1, t2)
+            | Term::Mod(t1, t2) => {
+                self.int_find_unknown(t1, source, unknown);
+                self.int_find_unknown(t2, source, unknown);
+            }
+            Term::Bool(_)
+            | Term::Int(_)
+            | Term::Float(_)
+            | Term::String(_)
+            | Term::Regex(..)
+            | Term::LoadedTerm(_) => {}
+        }
+    }
+
+    /// Compile every regex literal in ```t```, so that an invalid
+    /// pattern fails the compilation rather than the evaluation
+    fn int_check_regexes(&self, t: &Term) -> Result<()> {
+        match t {
+            Term::Regex(rval, flags) => flags.build(rval).map(|_| ()),
+            Term::Not(inner) | Term::Neg(inner) | Term::IsNull(inner) => {
+                self.int_check_regexes(inner)
+            }
+            Term::In(subject, list) => {
+                self.int_check_regexes(subject)?;
+                list.iter()
+                    .try_for_each(|element| self.int_check_regexes(element))
+            }
+            Term::Eq(t1, t2)
+            | Term::Or(t1, t2)
+            | Term::And(t1, t2)
+            | Term::Lt(t1, t2)
+            | Term::Le(t1, t2)
+            | Term::Gt(t1, t2)
+            | Term::Ge(t1, t2)
+            | Term::Matches(t1, t2)
+            | Term::Add(t1, t2)
+            | Term::Sub(t1, t2)
+            | Term::Mul(t1, t2)
+            | Term::Div(t1, t2)
+            | Term::Mod(t1, t2) => {
+                self.int_check_regexes(t1)?;
+                self.int_check_regexes(t2)
+            }
+            Term::Bool(_)
+            | Term::Int(_)
+            | Term::Float(_)
+            | Term::String(_)
+            | Term::Variable(_)
+            | Term::LoadedTerm(_) => Ok(()),
+        }
+    }
+
+    /// Declared type of the variable ```var_name```, if it is typed
+    fn int_var_type(&self, var_name: &str) -> Option<Type> {
+        self.variables
+            .get(var_name)
+            .and_then(|slot| self.types.get(slot))
+            .cloned()
+    }
+
+    /// Determine the static type of ```t```, failing if ```t``` uses
+    /// an operator on operands whose types are known not to fit.
+    /// Untyped variables are checked at runtime instead.
+    fn int_type_of(&self, t: &Term) -> Result<StaticType> {
+        match t {
+            Term::Bool(_) => Ok(StaticType::Known(Type::Bool)),
+            Term::Int(_) => Ok(StaticType::Known(Type::I64)),
+            Term::Float(_) => Ok(StaticType::Known(Type::F64)),
+            Term::String(_) => Ok(StaticType::Known(Type::String)),
+            Term::Regex(..) => Ok(StaticType::Regex),
+            Term::Variable(var_name) => Ok(self
+                .int_var_type(var_name)
+                .map(StaticType::Known)
+                .unwrap_or(StaticType::Dynamic)),
+            Term::LoadedTerm(_) => Ok(StaticType::Dynamic),
+            Term::Not(inner) => {
+                self.int_expect_bool(inner, "operand of not")?;
+                Ok(StaticType::Known(Type::Bool))
+            }
+            Term::And(t1, t2) | Term::Or(t1, t2) => {
+                let what = if let Term::And(_, _) = t {
+                    "operand of and"
+                } else {
+                    "operand of or"
+                };
+                self.int_expect_bool(t1, what)?;
+                self.int_expect_bool(t2, what)?;
+                Ok(StaticType::Known(Type::Bool))
+            }
+            Term::IsNull(inner) => {
+                // Any value may be null
+                self.int_type_of(inner)?;
+                Ok(StaticType::Known(Type::Bool))
+            }
+            Term::Eq(t1, t2) => {
+                let (ty1, ty2) = (self.int_type_of(t1)?, self.int_type_of(t2)?);
+                if !comparable(ty1, ty2) {
+                    return Err(type_mismatch("==", ty1, ty2));
+                }
+                Ok(StaticType::Known(Type::Bool))
+            }
+            Term::In(subject, list) => {
+                let ty1 = self.int_type_of(subject)?;
+                for element in list {
+                    match element {
+                        Term::Bool(_) | Term::Int(_) | Term::Float(_) | Term::String(_) => {}
+                        other => {
+                            return Err(Error::UnsupportedOperator(format!(
+                                "in needs a list of literals, got {}",
+                                other
+                            )))
+                        }
+                    }
+                    let ty2 = self.int_type_of(element)?;
+                    if !comparable(ty1, ty2) {
+                        return Err(type_mismatch("in", ty1, ty2));
+                    }
+                }
+                Ok(StaticType::Known(Type::Bool))
+            }
+            Term::Matches(t1, t2) => {
+                let (ty1, ty2) = (self.int_type_of(t1)?, self.int_type_of(t2)?);
+                let matchable = match (ty1, ty2) {
+                    (StaticType::Dynamic, StaticType::Regex) => true,
+                    (StaticType::Known(ty), StaticType::Regex) => ty.is_text(),
+                    _ => false,
+                };
+                if !matchable {
+                    return Err(type_mismatch("=~", ty1, ty2));
+                }
+                Ok(StaticType::Known(Type::Bool))
+            }
+            Term::Lt(t1, t2) | Term::Le(t1, t2) | Term::Gt(t1, t2) | Term::Ge(t1, t2) => {
+                let (ty1, ty2) = (self.int_type_of(t1)?, self.int_type_of(t2)?);
+                let ordered = match (ty1, ty2) {
+                    (StaticType::Dynamic, StaticType::Dynamic) => true,
+                    (StaticType::Dynamic, StaticType::Known(ty))
+                    | (StaticType::Known(ty), StaticType::Dynamic) => ty != Type::Bool,
+                    (StaticType::Known(ty1), StaticType::Known(ty2)) => {
+                        (ty1.is_numeric() && ty2.is_numeric())
+                            || (ty1.is_text() && ty2.is_text())
+                            || (ty1 == Type::Timestamp && ty2 == Type::Timestamp)
+                    }
+                    _ => false,
+                };
+                if !ordered {
+                    let operator = match t {
+                        Term::Lt(_, _) => "<",
+                        Term::Le(_, _) => "<=",
+                        Term::Gt(_, _) => ">",
+                        _ => ">=",
+                    };
+                    return Err(type_mismatch(operator, ty1, ty2));
+                }
+                Ok(StaticType::Known(Type::Bool))
+            }
+            Term::Neg(inner) => match self.int_type_of(inner)? {
+                StaticType::Known(ty) if ty.is_numeric() => Ok(StaticType::Known(ty)),
+                StaticType::Dynamic => Ok(StaticType::Dynamic),
+                other => Err(Error::TypeMismatch(format!(
+                    "cannot negate {}",
+                    describe(other)
+                ))),
+            },
+            Term::Add(t1, t2)
+            | Term::Sub(t1, t2)
+            | Term::Mul(t1, t2)
+            | Term::Div(t1, t2)
+            | Term::Mod(t1, t2) => {
+                let (ty1, ty2) = (self.int_type_of(t1)?, self.int_type_of(t2)?);
+                match (ty1, ty2) {
+                    (StaticType::Known(ty1), StaticType::Known(ty2))
+                        if ty1.is_numeric() && ty2.is_numeric() =>
+                    {
+                        Ok(StaticType::Known(promote(ty1, ty2)))
+                    }
+                    (StaticType::Dynamic, StaticType::Known(ty))
+                    | (StaticType::Known(ty), StaticType::Dynamic)
+                        if ty.is_numeric() =>
+                    {
+                        Ok(StaticType::Dynamic)
+                    }
+                    (StaticType::Dynamic, StaticType::Dynamic) => Ok(StaticType::Dynamic),
+                    _ => {
+                        let (op, _, _) = Arith::of(t).expect("arithmetic term");
+                        Err(type_mismatch(op.symbol(), ty1, ty2))
+                    }
+                }
+            }
+        }
+    }
+
+    /// Type check ```t```, which must be a boolean since it is
+    /// ```what```
+    fn int_expect_bool(&self, t: &Term, what: &str) -> Result<()> {
+        if let Term::Variable(_) = t {
+            // Bare variables of any type are tested for truthiness
+            return Ok(());
+        }
+        match self.int_type_of(t)? {
+            StaticType::Known(Type::Bool) | StaticType::Dynamic => Ok(()),
+            other => Err(Error::TypeMismatch(format!(
+                "{} must be a boolean, not {}",
+                what,
+                describe(other)
+            ))),
+        }
+    }
+
+    /// An operand that can be used natively, i.e. a numeric literal
+    /// or a natively stored typed variable, along with the type it
+    /// is stored as.
+    fn int_native_operand(&self, t: &Term) -> Option<(Term<'a>, Type)> {
+        match t {
+            Term::Bool(bval) => Some((Term::Bool(*bval), Type::I64)),
+            Term::Float(fval) => Some((Term::Float(*fval), Type::F64)),
+            Term::Int(intval) => Some((Term::Int(*intval), Type::I64)),
+            Term::Variable(var_name) => {
+                let (key, slot) = self.variables.get_key_value(&**var_name)?;
+                let (ty, _) = self.native.get(slot)?;
+                Some((Term::Variable(Cow::Borrowed(*key)), ty.storage()?))
+            }
+            Term::Neg(inner) => {
+                let (native, ty) = self.int_native_operand(inner)?;
+                Some((Term::Neg(Box::new(native)), ty))
+            }
+            _ => {
+                let (op, t1, t2) = Arith::of(t)?;
+                let (native1, ty1) = self.int_native_operand(t1)?;
+                let (native2, ty2) = self.int_native_operand(t2)?;
+                Some((op.make(native1, native2), promote(ty1, ty2)))
+            }
+        }
+    }
+
+    /// Compile an AST to a wasm representation that needs to be instantiated
+    /// subsequently. All variables referenced by the AST *must be* defined
+    /// by a call to define_var before ```compile``` is called, unless
+    /// the context is in lenient mode.
+    pub fn compile(&self, t: &Term) -> Result<CompiledTerm> {
+        self.int_compile_term(t, None)
+    }
+
+    /// Like ```compile```, but additionally takes the formula source the
+    /// AST was parsed from, so that errors can report the position of
+    /// unknown identifiers.
+    pub fn compile_with_source(&self, t: &Term, source: &str) -> Result<CompiledTerm> {
+        self.int_compile_term(t, Some(source))
+    }
+
+    fn int_compile_term(&self, t: &Term, source: Option<&str>) -> Result<CompiledTerm> {
+        if !self.lenient {
+            let mut unknown = Vec::new();
+            self.int_find_unknown(t, source, &mut unknown);
+            if !unknown.is_empty() {
+                if let Some(source) = source {
+                    self.int_locate_unknown(source, &mut unknown);
+                }
+                return Err(Error::UnknownVariable(unknown));
+            }
+        }
+        self.int_expect_bool(t, "formula")?;
+        self.int_check_regexes(t)?;
+        let simplified = simplify(t);
+        let t = &simplified;
+
+        let mut compilation = Compilation::new(self);
+        if self.rtl_module.is_none() {
+            let program = Program::new(t, &mut |var_name| compilation.int_resolve_var(var_name))?;
+            return Ok(CompiledTerm {
+                backend: CompiledBackend::Interpreter(Arc::new(program)),
+                variables: self.int_variable_slots(),
+            });
+        }
+
+        compilation.int_compile_wasm(t)
+    }
+
+    /// A copy of the slots and types of the declared variables, for
+    /// the instances of a formula
+    fn int_variable_slots(&self) -> Arc<VariableSlots> {
+        Arc::new(VariableSlots {
+            slots: self
+                .variables
+                .iter()
+                .map(|(name, slot)| (name.to_string(), *slot))
+                .collect(),
+            types: self.types.clone(),
+        })
+    }
+}
+
+impl<'c, 'a> Compilation<'c, 'a> {
+    fn new(context: &'c Context<'a>) -> Compilation<'c, 'a> {
+        Compilation {
+            context,
+            instructions: Vec::new(),
+            idx_state: None,
+            idx_string: None,
+            idx_mem_buf_ptr: None,
+            eval_temps: 0,
+            locals: context.locals,
+            lenient_slots: Vec::new(),
+            dynamic_slots: Vec::new(),
+        }
+    }
+
+    /// Slot of the variable ```var_name```
+    fn int_resolve_var(&mut self, var_name: &str) -> Result<i64> {
+        match self.context.variables.get(var_name) {
+            Some(v) => Ok(*v),
+            None if self.context.lenient => {
+                // A slot that is never written to evaluates as null
+                let my_local_idx = self.locals;
+                self.locals += 1;
+                self.lenient_slots.push(my_local_idx);
+                Ok(my_local_idx)
+            }
+            None => Err(Error::UnknownVariable(vec![UnknownIdentifier {
+                name: var_name.to_string(),
+                offset: None,
+            }])),
+        }
+    }
+
+    /// Emit the loader code for ```t```. When ```native_ok``` is set,
+    /// ```t``` is in boolean position, so comparisons whose operands
+    /// are both native are kept as they are, to be compiled to native
+    /// instructions by int_compile_bool.
+    fn int_build_loader(&mut self, t: &Term, native_ok: bool) -> Result<Term<'a>> {
+        if native_ok {
+            let operands = match t {
+                Term::Eq(t1, t2)
+                | Term::Lt(t1, t2)
+                | Term::Le(t1, t2)
+                | Term::Gt(t1, t2)
+                | Term::Ge(t1, t2) => {
+                    match (
+                        self.context.int_native_operand(t1),
+                        self.context.int_native_operand(t2),
+                    ) {
+                        (Some((n1, _)), Some((n2, _))) => Some((Box::new(n1), Box::new(n2))),
+                        _ => None,
+                    }
+                }
+                _ => None,
+            };
+            if let Some((n1, n2)) = operands {
+                return Ok(match t {
+                    Term::Eq(_, _) => Term::Eq(n1, n2),
+                    Term::Lt(_, _) => Term::Lt(n1, n2),
+                    Term::Le(_, _) => Term::Le(n1, n2),
+                    Term::Gt(_, _) => Term::Gt(n1, n2),
+                    _ => Term::Ge(n1, n2),
+                });
+            }
+        }
+
         match &t {
+            &Term::Bool(bval) if native_ok => Ok(Term::Bool(*bval)),
+            &Term::Bool(bval) => {
+                // There is no runtime constructor for booleans, so
+                // compare a value with itself instead
+                let my_local_idx = self.locals;
+                self.locals += 1;
+                self.instructions
+                    .push(Instruction::GetLocal(self.idx_state.expect("P4")));
+                self.instructions.push(Instruction::I64Const(my_local_idx));
+                self.instructions.push(Instruction::I64Const(0));
+                self.instructions.push(Instruction::Call(FUN_MAKE_I64));
+                self.instructions.push(Instruction::Drop);
+                let loaded = Box::new(Term::LoadedTerm(my_local_idx));
+                let truth = Term::Eq(loaded.clone(), loaded);
+                Ok(if *bval {
+                    truth
+                } else {
+                    Term::Not(Box::new(truth))
+                })
+            }
             &Term::Int(intval) => {
                 let my_local_idx = self.locals;
                 self.locals += 1;
@@ -194,7 +1634,7 @@ impl<'a> Context<'a> {
                 self.instructions.push(Instruction::I64Const(*intval));
                 self.instructions.push(Instruction::Call(FUN_MAKE_I64));
                 self.instructions.push(Instruction::Drop);
-                Term::LoadedTerm(my_local_idx)
+                Ok(Term::LoadedTerm(my_local_idx))
             }
             &Term::Float(fval) => {
                 let my_local_idx = self.locals;
@@ -206,11 +1646,11 @@ impl<'a> Context<'a> {
                     .push(Instruction::F64Const(fval.to_bits()));
                 self.instructions.push(Instruction::Call(FUN_MAKE_F64));
                 self.instructions.push(Instruction::Drop);
-                Term::LoadedTerm(my_local_idx)
+                Ok(Term::LoadedTerm(my_local_idx))
             }
-            &Term::String(sval) => {
-                // TODO: max string length check as i32 != usize !
+            &Term::String(ref sval) => {
                 let string_bytes = sval.as_bytes();
+                check_literal_len(string_bytes)?;
                 let my_local_idx = self.locals;
                 self.locals += 1;
 
@@ -247,11 +1687,11 @@ impl<'a> Context<'a> {
                     .push(Instruction::I32Const(string_bytes.len() as i32));
                 self.instructions.push(Instruction::Call(FUN_MAKE_STRING));
                 self.instructions.push(Instruction::Drop);
-                Term::LoadedTerm(my_local_idx)
+                Ok(Term::LoadedTerm(my_local_idx))
             }
-            &Term::Regex(rval) => {
-                // TODO: max string length check as i32 != usize !
+            &Term::Regex(ref rval, flags) => {
                 let string_bytes = rval.as_bytes();
+                check_literal_len(string_bytes)?;
                 let my_local_idx = self.locals;
                 self.locals += 1;
 
@@ -278,161 +1718,1335 @@ impl<'a> Context<'a> {
                     i += 1;
                 }
 
-                let regex_flags = 0; // TODO
-
                 // Make a string out of the bytes :-)
                 self.instructions
                     .push(Instruction::GetLocal(self.idx_state.expect("P4")));
                 self.instructions.push(Instruction::I64Const(my_local_idx));
-                self.instructions.push(Instruction::I64Const(regex_flags));
+                self.instructions.push(Instruction::I64Const(flags.bits()));
                 self.instructions
                     .push(Instruction::GetLocal(self.idx_string.expect("P5")));
                 self.instructions
                     .push(Instruction::I32Const(string_bytes.len() as i32));
                 self.instructions.push(Instruction::Call(FUN_MAKE_REGEX));
                 self.instructions.push(Instruction::Drop);
-                Term::LoadedTerm(my_local_idx)
+                Ok(Term::LoadedTerm(my_local_idx))
             }
-            &Term::Variable(var_name) => match self.variables.get(*var_name) {
-                Some(v) => Term::LoadedTerm(*v),
-                None => Term::LoadedTerm(-1),
+            Term::Variable(var_name) => match self.context.int_native_operand(t) {
+                // Typed variables are boxed into a runtime value on use
+                Some((typed, _)) => Ok(typed),
+                None if native_ok => {
+                    // A bare variable in boolean position is tested for
+                    // truthiness. Without typed storage, a variable is
+                    // always a number or a string in the wasm backend.
+                    let slot = Box::new(Term::LoadedTerm(self.int_resolve_var(var_name)?));
+                    let empty = Box::new(self.int_build_loader(&Term::String("".into()), false)?);
+                    let is_empty = Box::new(Term::Eq(slot.clone(), empty));
+                    if self.context.int_var_type(var_name).is_some() {
+                        return Ok(Term::Not(is_empty));
+                    }
+                    let zero = Box::new(self.int_build_loader(&Term::Int(0), false)?);
+                    let is_zero = Box::new(Term::Eq(slot, zero));
+                    Ok(Term::Not(Box::new(Term::Or(is_zero, is_empty))))
+                }
+                None => Ok(Term::LoadedTerm(self.int_resolve_var(var_name)?)),
             },
-            &Term::LoadedTerm(_) => None.expect("Cannot double-build loader"),
+            &Term::LoadedTerm(_) => Err(Error::UnsupportedOperator(
+                "cannot double-build loader".to_string(),
+            )),
+            // Arithmetic is evaluated natively, there is no support for
+            // it in the runtime library
+            &Term::Add(_, _)
+            | &Term::Sub(_, _)
+            | &Term::Mul(_, _)
+            | &Term::Div(_, _)
+            | &Term::Mod(_, _)
+            | &Term::Neg(_) => self.int_build_dynamic(t),
+            &Term::IsNull(inner) => {
+                // A bare variable is tested itself rather than its truthiness
+                let inner_d = self.int_build_loader(inner, native_ok && is_truth_valued(inner))?;
+                Ok(Term::IsNull(Box::new(inner_d)))
+            }
             &Term::And(t1, t2) => {
-                let t1_d = self.int_build_loader(t1);
-                let t2_d = self.int_build_loader(t2);
-                Term::And(Box::new(t1_d), Box::new(t2_d))
+                let t1_d = self.int_build_loader(t1, native_ok)?;
+                let t2_d = self.int_build_loader(t2, native_ok)?;
+                Ok(Term::And(Box::new(t1_d), Box::new(t2_d)))
             }
-            &Term::Not(t) => Term::Not(Box::new(self.int_build_loader(t))),
+            &Term::Not(t) => Ok(Term::Not(Box::new(self.int_build_loader(t, native_ok)?))),
             &Term::Or(t1, t2) => {
-                let t1_d = self.int_build_loader(t1);
-                let t2_d = self.int_build_loader(t2);
-                Term::Or(Box::new(t1_d), Box::new(t2_d))
+                let t1_d = self.int_build_loader(t1, native_ok)?;
+                let t2_d = self.int_build_loader(t2, native_ok)?;
+                Ok(Term::Or(Box::new(t1_d), Box::new(t2_d)))
             }
             &Term::Eq(t1, t2) => {
-                let t1_d = self.int_build_loader(t1);
-                let t2_d = self.int_build_loader(t2);
-                Term::Eq(Box::new(t1_d), Box::new(t2_d))
+                // Truth-valued operands are compiled with int_compile_bool
+                let t1_d = self.int_build_loader(t1, is_truth_valued(t1))?;
+                let t2_d = self.int_build_loader(t2, is_truth_valued(t2))?;
+                Ok(Term::Eq(Box::new(t1_d), Box::new(t2_d)))
             }
+            // The runtime library cannot order values, orderings are
+            // evaluated natively on the operands' value cells
             &Term::Lt(t1, t2) => {
-                let t1_d = self.int_build_loader(t1);
-                let t2_d = self.int_build_loader(t2);
-                Term::Lt(Box::new(t1_d), Box::new(t2_d))
+                let t1_d = self.int_build_dynamic(t1)?;
+                let t2_d = self.int_build_dynamic(t2)?;
+                Ok(Term::Lt(Box::new(t1_d), Box::new(t2_d)))
             }
             &Term::Le(t1, t2) => {
-                let t1_d = self.int_build_loader(t1);
-                let t2_d = self.int_build_loader(t2);
-                Term::Le(Box::new(t1_d), Box::new(t2_d))
+                let t1_d = self.int_build_dynamic(t1)?;
+                let t2_d = self.int_build_dynamic(t2)?;
+                Ok(Term::Le(Box::new(t1_d), Box::new(t2_d)))
             }
             &Term::Gt(t1, t2) => {
-                let t1_d = self.int_build_loader(t1);
-                let t2_d = self.int_build_loader(t2);
-                Term::Gt(Box::new(t1_d), Box::new(t2_d))
+                let t1_d = self.int_build_dynamic(t1)?;
+                let t2_d = self.int_build_dynamic(t2)?;
+                Ok(Term::Gt(Box::new(t1_d), Box::new(t2_d)))
             }
             &Term::Ge(t1, t2) => {
-                let t1_d = self.int_build_loader(t1);
-                let t2_d = self.int_build_loader(t2);
-                Term::Ge(Box::new(t1_d), Box::new(t2_d))
+                let t1_d = self.int_build_dynamic(t1)?;
+                let t2_d = self.int_build_dynamic(t2)?;
+                Ok(Term::Ge(Box::new(t1_d), Box::new(t2_d)))
+            }
+            &Term::Matches(t1, t2) => {
+                let t1_d = self.int_build_loader(t1, false)?;
+                let t2_d = self.int_build_loader(t2, false)?;
+                Ok(Term::Matches(Box::new(t1_d), Box::new(t2_d)))
+            }
+            &Term::In(subject, list) => {
+                let elements: Option<Vec<Term<'a>>> = list
+                    .iter()
+                    .map(|element| match element {
+                        Term::Bool(_) | Term::Int(_) | Term::Float(_) => self
+                            .context
+                            .int_native_operand(element)
+                            .map(|(native, _)| native),
+                        _ => None,
+                    })
+                    .collect();
+                if let (Some((native, _)), Some(elements)) =
+                    (self.context.int_native_operand(subject), elements)
+                {
+                    return Ok(Term::In(Box::new(native), elements));
+                }
+                if is_truth_valued(subject) {
+                    // The subject's truth is compared with the booleans
+                    let subject_d = self.int_build_loader(subject, true)?;
+                    let booleans = list
+                        .iter()
+                        .filter_map(|element| match element {
+                            Term::Bool(bval) => Some(Term::Bool(*bval)),
+                            _ => None,
+                        })
+                        .collect();
+                    return Ok(Term::In(Box::new(subject_d), booleans));
+                }
+
+                // Numbers are kept as native literals, strings are stored
+                // in value cells. Both are sorted, so int_compile_in can
+                // search them.
+                let subject_d = self.int_build_dynamic(subject)?;
+                let mut numbers = Vec::new();
+                let mut texts = Vec::new();
+                for element in list {
+                    match element {
+                        Term::Int(intval) => numbers.push(Term::Int(*intval)),
+                        Term::Float(fval) => numbers.push(Term::Float(*fval)),
+                        Term::String(_) => texts.push(element),
+                        other => {
+                            return Err(Error::UnsupportedOperator(format!(
+                                "{} in a list of numbers and strings -- bug!",
+                                other
+                            )))
+                        }
+                    }
+                }
+                numbers.sort_by(|a, b| literal_order(a, b));
+                texts.sort_by(|a, b| literal_order(a, b));
+                for text in texts {
+                    numbers.push(self.int_build_dynamic(text)?);
+                }
+                Ok(Term::In(Box::new(subject_d), numbers))
+            }
+        }
+    }
+
+    /// Emit the loader code for an operand of an ordering, of a search
+    /// in a list or of arithmetic, which int_compile_dynamic compiles.
+    /// Native operands are kept as they are, strings are stored in the
+    /// value cell of a new slot and untyped variables are read from
+    /// theirs.
+    fn int_build_dynamic(&mut self, t: &Term) -> Result<Term<'a>> {
+        if let Some((native, _)) = self.context.int_native_operand(t) {
+            return Ok(native);
+        }
+        match t {
+            Term::String(sval) => {
+                let my_local_idx = self.locals;
+                self.locals += 1;
+                self.int_load_text_cell(my_local_idx, sval.as_bytes())?;
+                Ok(Term::LoadedTerm(my_local_idx))
+            }
+            Term::Variable(var_name) => {
+                let slot = self.int_resolve_var(var_name)?;
+                if !self.dynamic_slots.contains(&slot) {
+                    self.dynamic_slots.push(slot);
+                }
+                Ok(Term::LoadedTerm(slot))
+            }
+            Term::Neg(inner) => Ok(Term::Neg(Box::new(self.int_build_dynamic(inner)?))),
+            other => match Arith::of(other) {
+                Some((op, t1, t2)) => {
+                    let t1_d = self.int_build_dynamic(t1)?;
+                    let t2_d = self.int_build_dynamic(t2)?;
+                    Ok(op.make(t1_d, t2_d))
+                }
+                None => Err(Error::UnsupportedOperator(format!(
+                    "cannot order {:?}",
+                    other
+                ))),
+            },
+        }
+    }
+
+    /// Emit the loader code storing ```bytes``` in a buffer of their
+    /// own and the text in the value cell of ```slot```
+    fn int_load_text_cell(&mut self, slot: i64, bytes: &[u8]) -> Result<()> {
+        check_literal_len(bytes)?;
+        let ptr = self.idx_mem_buf_ptr.expect("P6");
+        self.instructions.extend(vec![
+            Instruction::I32Const(bytes.len() as i32),
+            Instruction::Call(FUN_ALLOC_STRING),
+            Instruction::Call(FUN_GET_STRING_BUF),
+            Instruction::SetLocal(ptr),
+        ]);
+        for (i, b) in bytes.iter().enumerate() {
+            self.instructions.push(Instruction::GetLocal(ptr));
+            self.instructions.push(Instruction::I32Const(*b as i32));
+            self.instructions.push(Instruction::I32Store8(0, i as u32));
+        }
+        let cell = slot as u32 * 16;
+        self.instructions.extend(vec![
+            Instruction::GetGlobal(GLOBAL_CELLS),
+            Instruction::I32Const(VALUE_TEXT),
+            Instruction::I32Store(2, cell),
+            Instruction::GetGlobal(GLOBAL_CELLS),
+            Instruction::GetLocal(ptr),
+            Instruction::I64ExtendUI32,
+            Instruction::I64Const((bytes.len() as i64) << 32),
+            Instruction::I64Or,
+            Instruction::I64Store(3, cell + 8),
+        ]);
+        Ok(())
+    }
+
+    fn int_compile(&mut self, t: &Term) -> Result<()> {
+        match &t {
+            &Term::Bool(_) | &Term::Int(_) => {
+                return Err(Error::UnsupportedOperator(
+                    "only compiled terms are supported -- bug!".to_string(),
+                ));
+            }
+            &Term::Float(_fval) => {
+                return Err(Error::UnsupportedOperator(
+                    "only compiled terms are supported -- bug!".to_string(),
+                ));
+            }
+            &Term::String(ref _sval) => {
+                return Err(Error::UnsupportedOperator(
+                    "only compiled terms are supported -- bug!".to_string(),
+                ));
+            }
+            &Term::Regex(..) => {
+                return Err(Error::UnsupportedOperator(
+                    "only compiled terms are supported -- bug!".to_string(),
+                ));
+            }
+            Term::Variable(var_name) => {
+                // A typed variable in value position, box it
+                let not_typed = || {
+                    Error::UnsupportedOperator(
+                        "only compiled terms are supported -- bug!".to_string(),
+                    )
+                };
+                let slot = *self
+                    .context
+                    .variables
+                    .get(&**var_name)
+                    .ok_or_else(not_typed)?;
+                let (ty, cell) = *self.context.native.get(&slot).ok_or_else(not_typed)?;
+                self.instructions.push(Instruction::GetLocal(
+                    self.idx_state.expect("State should be initialized!"),
+                ));
+                self.instructions.push(Instruction::I64Const(slot));
+                self.int_load_native(ty, cell);
+                self.instructions
+                    .push(Instruction::Call(match ty.storage() {
+                        Some(Type::F64) => FUN_MAKE_F64,
+                        _ => FUN_MAKE_I64,
+                    }));
+            }
+            &Term::LoadedTerm(idx) => {
+                self.instructions.push(Instruction::I64Const(*idx));
+            }
+            &Term::Add(_, _)
+            | &Term::Sub(_, _)
+            | &Term::Mul(_, _)
+            | &Term::Div(_, _)
+            | &Term::Mod(_, _)
+            | &Term::Neg(_) => {
+                // Arithmetic in value position, box its result
+                let ty = match self.context.int_native_operand(t) {
+                    Some((_, ty)) => ty,
+                    None => return self.int_box_dynamic(t),
+                };
+                let my_local_idx = self.locals;
+                self.locals += 1;
+                self.instructions.push(Instruction::GetLocal(
+                    self.idx_state.expect("State should be initialized!"),
+                ));
+                self.instructions.push(Instruction::I64Const(my_local_idx));
+                self.int_compile_native(t, ty)?;
+                self.instructions.push(Instruction::Call(match ty {
+                    Type::F64 => FUN_MAKE_F64,
+                    _ => FUN_MAKE_I64,
+                }));
+            }
+            &Term::Not(inner) => {
+                self.instructions.push(Instruction::GetLocal(
+                    self.idx_state.expect("State should be initialized!"),
+                ));
+                self.int_compile(inner)?;
+                self.instructions.push(Instruction::Call(FUN_RTL_NOT));
+            }
+            &Term::And(left, right) => self.int_compile_connective(left, right, FUN_RTL_AND)?,
+            &Term::Or(left, right) => self.int_compile_connective(left, right, FUN_RTL_OR)?,
+            &Term::Eq(left, right) => {
+                self.instructions.push(Instruction::GetLocal(
+                    self.idx_state.expect("State should be initialized!"),
+                ));
+                self.int_compile(left)?;
+                self.int_compile(right)?;
+                self.instructions.push(Instruction::Call(FUN_RTL_EQ));
+            }
+            &Term::Lt(_, _) | &Term::Le(_, _) | &Term::Gt(_, _) | &Term::Ge(_, _) => {
+                self.int_compile_order(t)?;
+                self.int_box_truth();
+            }
+            &Term::Matches(left, right) => {
+                // wormrtl's eq matches the text against the regex, the
+                // type checker keeps regexes out of ==
+                self.instructions.push(Instruction::GetLocal(
+                    self.idx_state.expect("State should be initialized!"),
+                ));
+                self.int_compile(left)?;
+                self.int_compile(right)?;
+                self.instructions.push(Instruction::Call(FUN_RTL_EQ));
+            }
+            &Term::IsNull(_) => {
+                self.int_compile_bool(t)?;
+                self.instructions.push(Instruction::I32Const(TRUTH_TRUE));
+                self.instructions.push(Instruction::I32Eq);
+                self.int_box_truth();
             }
+            &Term::In(subject, list) => {
+                self.int_compile_in(subject, list)?;
+                self.int_box_truth();
+            }
+        }
+        Ok(())
+    }
+
+    /// Compile ```subject in (keys)``` in boolean position, leaving an
+    /// i32 on the stack. The keys are native literals, booleans compared
+    /// with the truth of a truth-valued subject, or numbers and strings
+    /// that int_build_loader has sorted. Instead of comparing the
+    /// subject with every key, a balanced tree of ```<``` comparisons
+    /// narrows them down to a few that are tested for equality.
+    fn int_compile_in(&mut self, subject: &Term, keys: &[Term]) -> Result<()> {
+        let literals = keys.iter().all(|key| match key {
+            Term::Bool(_) | Term::Int(_) | Term::Float(_) => true,
+            _ => false,
+        });
+        let tmp = self.int_eval_temp();
+        if let (Some((_, ty)), true) = (self.context.int_native_operand(subject), literals) {
+            if ty == Type::I64 {
+                self.int_compile_native(subject, Type::I64)?;
+                self.instructions.push(Instruction::SetLocal(tmp));
+                return self.int_compile_int_search(tmp, keys);
+            }
+            self.int_compile_native(subject, Type::F64)?;
+            self.instructions.push(Instruction::I64ReinterpretF64);
+            self.instructions.push(Instruction::SetLocal(tmp));
+            return self.int_compile_search(tmp, &float_keys(keys), Some(Type::F64));
+        }
+        if is_truth_valued(subject) {
+            self.int_compile_bool(subject)?;
+            self.instructions.extend(vec![
+                Instruction::I32Const(TRUTH_TRUE),
+                Instruction::I32Eq,
+                Instruction::I64ExtendUI32,
+                Instruction::SetLocal(tmp),
+            ]);
+            return self.int_compile_search(tmp, &int_keys(keys), Some(Type::I64));
+        }
+
+        let (tag, value) = self.int_compile_dynamic(subject)?;
+        let (texts, numbers): (Vec<&Term>, Vec<&Term>) = keys.iter().partition(|key| match key {
+            Term::LoadedTerm(_) => true,
+            _ => false,
+        });
+        let numbers: Vec<Term> = numbers.into_iter().cloned().collect();
+        let texts: Vec<Term> = texts.into_iter().cloned().collect();
+        if numbers.is_empty() && texts.is_empty() {
+            self.instructions.push(Instruction::I32Const(0));
+        }
+        if !numbers.is_empty() {
+            self.int_push_is(tag, VALUE_I64);
+            self.instructions
+                .push(Instruction::If(BlockType::Value(ValueType::I32)));
+            self.int_compile_int_search(value, &numbers)?;
+            self.instructions.push(Instruction::Else);
+            self.int_push_is(tag, VALUE_F64);
+            self.instructions
+                .push(Instruction::If(BlockType::Value(ValueType::I32)));
+            self.int_compile_search(value, &float_keys(&numbers), Some(Type::F64))?;
+            self.instructions.push(Instruction::Else);
+            self.instructions.push(Instruction::I32Const(0));
+            self.instructions.push(Instruction::End);
+            self.instructions.push(Instruction::End);
+        }
+        if !texts.is_empty() {
+            self.int_push_is(tag, VALUE_TEXT);
+            self.instructions
+                .push(Instruction::If(BlockType::Value(ValueType::I32)));
+            self.int_compile_search(value, &texts, None)?;
+            self.instructions.push(Instruction::Else);
+            self.instructions.push(Instruction::I32Const(0));
+            self.instructions.push(Instruction::End);
+            if !numbers.is_empty() {
+                self.instructions.push(Instruction::I32Or);
+            }
+        }
+        Ok(())
+    }
+
+    /// Emit the search of int_compile_in for the i64 in ```value```.
+    /// Integer keys are compared exactly, float keys with the value
+    /// converted to f64.
+    fn int_compile_int_search(&mut self, value: u32, keys: &[Term]) -> Result<()> {
+        let (floats, integers): (Vec<&Term>, Vec<&Term>) = keys.iter().partition(|key| match key {
+            Term::Float(_) => true,
+            _ => false,
+        });
+        let floats: Vec<Term> = floats.into_iter().cloned().collect();
+        let integers: Vec<Term> = integers.into_iter().cloned().collect();
+        if floats.is_empty() || !integers.is_empty() {
+            self.int_compile_search(value, &int_keys(&integers), Some(Type::I64))?;
+        }
+        if !floats.is_empty() {
+            let tmp = self.int_eval_temp();
+            self.instructions.extend(vec![
+                Instruction::GetLocal(value),
+                Instruction::F64ConvertSI64,
+                Instruction::I64ReinterpretF64,
+                Instruction::SetLocal(tmp),
+            ]);
+            self.int_compile_search(tmp, &float_keys(&floats), Some(Type::F64))?;
+            if !integers.is_empty() {
+                self.instructions.push(Instruction::I32Or);
+            }
+        }
+        Ok(())
+    }
+
+    /// Emit the search of int_compile_in for the sorted ```keys```
+    fn int_compile_search(&mut self, tmp: u32, keys: &[Term], native: Option<Type>) -> Result<()> {
+        if keys.len() <= 4 {
+            if keys.is_empty() {
+                self.instructions.push(Instruction::I32Const(0));
+            }
+            for (i, key) in keys.iter().enumerate() {
+                self.int_compare_key(tmp, key, native, false)?;
+                if i > 0 {
+                    self.instructions.push(Instruction::I32Or);
+                }
+            }
+            return Ok(());
+        }
+        let mid = keys.len() / 2;
+        self.int_compare_key(tmp, &keys[mid], native, true)?;
+        self.instructions
+            .push(Instruction::If(BlockType::Value(ValueType::I32)));
+        self.int_compile_search(tmp, &keys[..mid], native)?;
+        self.instructions.push(Instruction::Else);
+        self.int_compile_search(tmp, &keys[mid..], native)?;
+        self.instructions.push(Instruction::End);
+        Ok(())
+    }
+
+    /// Push whether the value in ```tmp``` is less than (if ```less```
+    /// is set) or equal to ```key``` as an i32
+    fn int_compare_key(
+        &mut self,
+        tmp: u32,
+        key: &Term,
+        native: Option<Type>,
+        less: bool,
+    ) -> Result<()> {
+        match native {
+            Some(Type::I64) => {
+                self.instructions.push(Instruction::GetLocal(tmp));
+                self.int_compile_native(key, Type::I64)?;
+                self.instructions.push(if less {
+                    Instruction::I64LtS
+                } else {
+                    Instruction::I64Eq
+                });
+            }
+            Some(_) => {
+                self.instructions.push(Instruction::GetLocal(tmp));
+                self.instructions.push(Instruction::F64ReinterpretI64);
+                self.int_compile_native(key, Type::F64)?;
+                self.instructions.push(if less {
+                    Instruction::F64Lt
+                } else {
+                    Instruction::F64Eq
+                });
+            }
+            None => {
+                // Texts are compared with the value cell of the key
+                let slot = match key {
+                    Term::LoadedTerm(slot) => *slot as u32,
+                    _ => {
+                        return Err(Error::UnsupportedOperator(
+                            "unloaded in list key -- bug!".to_string(),
+                        ))
+                    }
+                };
+                self.instructions.extend(vec![
+                    Instruction::GetLocal(tmp),
+                    Instruction::GetGlobal(GLOBAL_CELLS),
+                    Instruction::I64Load(3, slot * 16 + 8),
+                    Instruction::Call(FUN_COMPARE_TEXT),
+                ]);
+                if less {
+                    self.instructions.push(Instruction::I32Const(0));
+                    self.instructions.push(Instruction::I32LtS);
+                } else {
+                    self.instructions.push(Instruction::I32Eqz);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Turn the 0 or 1 on the stack into a runtime boolean. There is
+    /// no runtime constructor for booleans, so compare a value with
+    /// itself and negate that if the stack holds 0.
+    fn int_box_truth(&mut self) {
+        let state = self.idx_state.expect("State should be initialized!");
+        let my_local_idx = self.locals;
+        self.locals += 1;
+        let (known, truth) = (self.int_eval_temp(), self.int_eval_temp());
+        self.instructions.extend(vec![
+            Instruction::I64ExtendUI32,
+            Instruction::SetLocal(known),
+            Instruction::GetLocal(state),
+            Instruction::I64Const(my_local_idx),
+            Instruction::I64Const(0),
+            Instruction::Call(FUN_MAKE_I64),
+            Instruction::Drop,
+            Instruction::GetLocal(state),
+            Instruction::I64Const(my_local_idx),
+            Instruction::I64Const(my_local_idx),
+            Instruction::Call(FUN_RTL_EQ),
+            Instruction::SetLocal(truth),
+            Instruction::GetLocal(known),
+            Instruction::I32WrapI64,
+            Instruction::If(BlockType::Value(ValueType::I64)),
+            Instruction::GetLocal(truth),
+            Instruction::Else,
+            Instruction::GetLocal(state),
+            Instruction::GetLocal(truth),
+            Instruction::Call(FUN_RTL_NOT),
+            Instruction::End,
+        ]);
+    }
+
+    /// Compile an operand of an ordering. Returns the i64 locals
+    /// holding its VALUE_ tag and its value as stored in a value cell.
+    fn int_compile_dynamic(&mut self, t: &Term) -> Result<(u32, u32)> {
+        let (tag, value) = (self.int_eval_temp(), self.int_eval_temp());
+        if let Some((_, ty)) = self.context.int_native_operand(t) {
+            let value_tag = if ty == Type::I64 {
+                self.int_compile_native(t, Type::I64)?;
+                VALUE_I64
+            } else {
+                self.int_compile_native(t, Type::F64)?;
+                self.instructions.push(Instruction::I64ReinterpretF64);
+                VALUE_F64
+            };
+            self.instructions.extend(vec![
+                Instruction::SetLocal(value),
+                Instruction::I64Const(value_tag as i64),
+                Instruction::SetLocal(tag),
+            ]);
+            return Ok((tag, value));
+        }
+        match t {
+            Term::LoadedTerm(slot) => {
+                let cell = *slot as u32 * 16;
+                self.instructions.extend(vec![
+                    Instruction::GetGlobal(GLOBAL_CELLS),
+                    Instruction::I64Load32U(2, cell),
+                    Instruction::SetLocal(tag),
+                    Instruction::GetGlobal(GLOBAL_CELLS),
+                    Instruction::I64Load(3, cell + 8),
+                    Instruction::SetLocal(value),
+                ]);
+                Ok((tag, value))
+            }
+            Term::Neg(inner) => {
+                let (inner_tag, inner_value) = self.int_compile_dynamic(inner)?;
+                self.int_push_is(inner_tag, VALUE_I64);
+                self.instructions.extend(vec![
+                    Instruction::If(BlockType::Value(ValueType::I64)),
+                    // Wraps around for i64::min_value()
+                    Instruction::I64Const(0),
+                    Instruction::GetLocal(inner_value),
+                    Instruction::I64Sub,
+                    Instruction::Else,
+                    Instruction::GetLocal(inner_value),
+                    Instruction::F64ReinterpretI64,
+                    Instruction::F64Neg,
+                    Instruction::I64ReinterpretF64,
+                    Instruction::End,
+                    Instruction::SetLocal(value),
+                ]);
+                self.int_push_is_number(inner_tag);
+                self.instructions.push(Instruction::If(BlockType::NoResult));
+                self.instructions.push(Instruction::GetLocal(inner_tag));
+                self.instructions.push(Instruction::SetLocal(tag));
+                self.instructions.push(Instruction::Else);
+                self.int_flag_not_a_number(tag);
+                self.instructions.push(Instruction::End);
+                Ok((tag, value))
+            }
+            _ => {
+                let (op, t1, t2) = Arith::of(t).ok_or_else(|| {
+                    Error::UnsupportedOperator("unloaded dynamic operand -- bug!".to_string())
+                })?;
+                let (ta, a) = self.int_compile_dynamic(t1)?;
+                let (tb, b) = self.int_compile_dynamic(t2)?;
+                // Integers stay integers, other numbers are promoted to
+                // f64 and anything else is an error
+                self.instructions
+                    .push(Instruction::I64Const(VALUE_I64 as i64));
+                self.instructions
+                    .push(Instruction::I64Const(VALUE_F64 as i64));
+                self.int_push_is(ta, VALUE_I64);
+                self.int_push_is(tb, VALUE_I64);
+                self.instructions.push(Instruction::I32And);
+                self.instructions.push(Instruction::Select);
+                self.instructions.push(Instruction::I64Const(0));
+                self.int_push_is_number(ta);
+                self.int_push_is_number(tb);
+                self.instructions.push(Instruction::I32And);
+                self.instructions.push(Instruction::Select);
+                self.instructions.push(Instruction::SetLocal(tag));
+
+                self.int_push_is(tag, VALUE_I64);
+                self.instructions
+                    .push(Instruction::If(BlockType::Value(ValueType::I64)));
+                self.int_emit_arith(op, Type::I64, a, b);
+                self.instructions.push(Instruction::Else);
+                self.int_push_is(tag, VALUE_F64);
+                self.instructions
+                    .push(Instruction::If(BlockType::Value(ValueType::I64)));
+                let (fa, fb) = (self.int_eval_temp(), self.int_eval_temp());
+                self.int_push_f64(ta, a);
+                self.instructions.push(Instruction::I64ReinterpretF64);
+                self.instructions.push(Instruction::SetLocal(fa));
+                self.int_push_f64(tb, b);
+                self.instructions.push(Instruction::I64ReinterpretF64);
+                self.instructions.push(Instruction::SetLocal(fb));
+                self.int_emit_arith(op, Type::F64, fa, fb);
+                self.instructions.push(Instruction::I64ReinterpretF64);
+                self.instructions.push(Instruction::Else);
+                self.int_flag_not_a_number(tag);
+                self.instructions.push(Instruction::I64Const(0));
+                self.instructions.push(Instruction::End);
+                self.instructions.push(Instruction::End);
+                self.instructions.push(Instruction::SetLocal(value));
+                Ok((tag, value))
+            }
+        }
+    }
+
+    /// Flag arithmetic on a value that is not a number as an error and
+    /// clear the VALUE_ tag in ```tag```
+    fn int_flag_not_a_number(&mut self, tag: u32) {
+        self.instructions.extend(vec![
+            Instruction::I32Const(ERROR_NOT_A_NUMBER),
+            Instruction::SetGlobal(GLOBAL_ERROR),
+            Instruction::I64Const(0),
+            Instruction::SetLocal(tag),
+        ]);
+    }
+
+    /// Compile arithmetic on untyped operands in value position, boxing
+    /// its result as a runtime value
+    fn int_box_dynamic(&mut self, t: &Term) -> Result<()> {
+        let state = self.idx_state.expect("State should be initialized!");
+        let (tag, value) = self.int_compile_dynamic(t)?;
+        let my_local_idx = self.locals;
+        self.locals += 1;
+        self.int_push_is(tag, VALUE_F64);
+        self.instructions.extend(vec![
+            Instruction::If(BlockType::Value(ValueType::I64)),
+            Instruction::GetLocal(state),
+            Instruction::I64Const(my_local_idx),
+            Instruction::GetLocal(value),
+            Instruction::F64ReinterpretI64,
+            Instruction::Call(FUN_MAKE_F64),
+            Instruction::Else,
+            Instruction::GetLocal(state),
+            Instruction::I64Const(my_local_idx),
+            Instruction::GetLocal(value),
+            Instruction::Call(FUN_MAKE_I64),
+            Instruction::End,
+        ]);
+        Ok(())
+    }
+
+    /// Push whether the VALUE_ tag in ```tag``` is ```value_tag```
+    fn int_push_is(&mut self, tag: u32, value_tag: i32) {
+        self.instructions.push(Instruction::GetLocal(tag));
+        self.instructions
+            .push(Instruction::I64Const(value_tag as i64));
+        self.instructions.push(Instruction::I64Eq);
+    }
+
+    /// Push whether the VALUE_ tag in ```tag``` is a number
+    fn int_push_is_number(&mut self, tag: u32) {
+        self.int_push_is(tag, VALUE_I64);
+        self.int_push_is(tag, VALUE_F64);
+        self.instructions.push(Instruction::I32Or);
+    }
+
+    /// Push the number with the VALUE_ tag in ```tag``` and the value in
+    /// ```value``` as f64
+    fn int_push_f64(&mut self, tag: u32, value: u32) {
+        self.instructions.extend(vec![
+            Instruction::GetLocal(value),
+            Instruction::F64ConvertSI64,
+            Instruction::GetLocal(value),
+            Instruction::F64ReinterpretI64,
+        ]);
+        self.int_push_is(tag, VALUE_I64);
+        self.instructions.push(Instruction::Select);
+    }
+
+    /// Compile an ordering of two operands, leaving 0 or 1 on the
+    /// stack. Integers are compared exactly, numbers of mixed types as
+    /// f64 and texts bytewise; values of different kinds are not
+    /// ordered.
+    fn int_compile_order(&mut self, t: &Term) -> Result<()> {
+        let (left, right, ops) = match t {
+            Term::Lt(left, right) => (
+                left,
+                right,
+                (Instruction::I64LtS, Instruction::F64Lt, Instruction::I32LtS),
+            ),
+            Term::Le(left, right) => (
+                left,
+                right,
+                (Instruction::I64LeS, Instruction::F64Le, Instruction::I32LeS),
+            ),
+            Term::Gt(left, right) => (
+                left,
+                right,
+                (Instruction::I64GtS, Instruction::F64Gt, Instruction::I32GtS),
+            ),
+            Term::Ge(left, right) => (
+                left,
+                right,
+                (Instruction::I64GeS, Instruction::F64Ge, Instruction::I32GeS),
+            ),
+            _ => {
+                return Err(Error::UnsupportedOperator(
+                    "not an ordering -- bug!".to_string(),
+                ))
+            }
+        };
+        let (int_op, float_op, text_op) = ops;
+        let (ta, a) = self.int_compile_dynamic(left)?;
+        let (tb, b) = self.int_compile_dynamic(right)?;
+
+        self.int_push_is(ta, VALUE_I64);
+        self.int_push_is(tb, VALUE_I64);
+        self.instructions.push(Instruction::I32And);
+        self.instructions.extend(vec![
+            Instruction::If(BlockType::Value(ValueType::I32)),
+            Instruction::GetLocal(a),
+            Instruction::GetLocal(b),
+            int_op,
+            Instruction::Else,
+        ]);
+        self.int_push_is_number(ta);
+        self.int_push_is_number(tb);
+        self.instructions.push(Instruction::I32And);
+        self.instructions
+            .push(Instruction::If(BlockType::Value(ValueType::I32)));
+        self.int_push_f64(ta, a);
+        self.int_push_f64(tb, b);
+        self.instructions.push(float_op);
+        self.instructions.push(Instruction::Else);
+        self.int_push_is(ta, VALUE_TEXT);
+        self.int_push_is(tb, VALUE_TEXT);
+        self.instructions.push(Instruction::I32And);
+        self.instructions.extend(vec![
+            Instruction::If(BlockType::Value(ValueType::I32)),
+            Instruction::GetLocal(a),
+            Instruction::GetLocal(b),
+            Instruction::Call(FUN_COMPARE_TEXT),
+            Instruction::I32Const(0),
+            text_op,
+            Instruction::Else,
+            Instruction::I32Const(0),
+            Instruction::End,
+            Instruction::End,
+            Instruction::End,
+        ]);
+        Ok(())
+    }
+
+    /// Allocate an i64 local of the eval function
+    fn int_eval_temp(&mut self) -> u32 {
+        // Locals of eval are numbered after its state parameter
+        self.eval_temps += 1;
+        self.eval_temps
+    }
+
+    /// Compile ```left and right``` or ```left or right``` in value
+    /// position. The right operand is only evaluated if the left one
+    /// does not decide the result, which is then the left operand.
+    fn int_compile_connective(&mut self, left: &Term, right: &Term, fun: u32) -> Result<()> {
+        let state = self.idx_state.expect("State should be initialized!");
+        let tmp = self.int_eval_temp();
+
+        self.instructions.push(Instruction::GetLocal(state));
+        self.int_compile(left)?;
+        self.instructions.push(Instruction::TeeLocal(tmp));
+        self.instructions.push(Instruction::Call(FUN_RTL_GET_BOOL));
+        if fun == FUN_RTL_OR {
+            self.instructions.push(Instruction::I32Eqz);
+        }
+        self.instructions
+            .push(Instruction::If(BlockType::Value(ValueType::I64)));
+        self.instructions.push(Instruction::GetLocal(state));
+        self.instructions.push(Instruction::GetLocal(tmp));
+        self.int_compile(right)?;
+        self.instructions.push(Instruction::Call(fun));
+        self.instructions.push(Instruction::Else);
+        self.instructions.push(Instruction::GetLocal(tmp));
+        self.instructions.push(Instruction::End);
+        Ok(())
+    }
+
+    /// Push the value of the typed variable stored in ```cell```
+    fn int_load_native(&mut self, ty: Type, cell: u32) {
+        self.instructions
+            .push(Instruction::GetGlobal(GLOBAL_NATIVE_BASE));
+        self.instructions.push(match ty.storage() {
+            Some(Type::F64) => Instruction::F64Load(3, cell * 8),
+            _ => Instruction::I64Load(3, cell * 8),
+        });
+    }
+
+    /// Push a native operand, converting it to ```ty```
+    fn int_compile_native(&mut self, t: &Term, ty: Type) -> Result<()> {
+        let own_ty = match t {
+            Term::Bool(bval) => {
+                self.instructions.push(Instruction::I64Const(*bval as i64));
+                Type::I64
+            }
+            Term::Float(fval) => {
+                self.instructions
+                    .push(Instruction::F64Const(fval.to_bits()));
+                Type::F64
+            }
+            Term::Int(intval) => {
+                self.instructions.push(Instruction::I64Const(*intval));
+                Type::I64
+            }
+            Term::Variable(var_name) => {
+                let (own_ty, cell) = self
+                    .context
+                    .variables
+                    .get(&**var_name)
+                    .and_then(|slot| self.context.native.get(slot))
+                    .cloned()
+                    .ok_or_else(|| {
+                        Error::UnsupportedOperator(format!("{} is not a typed variable", var_name))
+                    })?;
+                self.int_load_native(own_ty, cell);
+                own_ty.storage().unwrap_or(Type::I64)
+            }
+            Term::Neg(inner) => {
+                let (_, own_ty) = self.context.int_native_operand(t).ok_or_else(not_native)?;
+                if own_ty == Type::I64 {
+                    // Wraps around for i64::min_value()
+                    self.instructions.push(Instruction::I64Const(0));
+                    self.int_compile_native(inner, own_ty)?;
+                    self.instructions.push(Instruction::I64Sub);
+                } else {
+                    self.int_compile_native(inner, own_ty)?;
+                    self.instructions.push(Instruction::F64Neg);
+                }
+                own_ty
+            }
+            _ => match Arith::of(t) {
+                Some((op, t1, t2)) => {
+                    let (_, own_ty) = self.context.int_native_operand(t).ok_or_else(not_native)?;
+                    self.int_compile_arith(op, t1, t2, own_ty)?;
+                    own_ty
+                }
+                None => {
+                    return Err(Error::UnsupportedOperator(
+                        "not a native operand -- bug!".to_string(),
+                    ))
+                }
+            },
+        };
+        if own_ty == Type::I64 && ty == Type::F64 {
+            self.instructions.push(Instruction::F64ConvertSI64);
+        }
+        Ok(())
+    }
+
+    /// Push the result of ```t1 op t2```, computed in ```ty```. See
+    /// ```Arith::apply_i64``` and ```Arith::apply_f64``` for the
+    /// semantics, which the code emitted here must match.
+    fn int_compile_arith(&mut self, op: Arith, t1: &Term, t2: &Term, ty: Type) -> Result<()> {
+        use parity_wasm::elements::Instruction::*;
+        let simple = match (op, ty) {
+            (Arith::Add, Type::I64) => Some(I64Add),
+            (Arith::Sub, Type::I64) => Some(I64Sub),
+            (Arith::Mul, Type::I64) => Some(I64Mul),
+            (Arith::Add, _) => Some(F64Add),
+            (Arith::Sub, _) => Some(F64Sub),
+            (Arith::Mul, _) => Some(F64Mul),
+            (Arith::Div, Type::F64) => Some(F64Div),
+            _ => None,
+        };
+        if let Some(instruction) = simple {
+            self.int_compile_native(t1, ty)?;
+            self.int_compile_native(t2, ty)?;
+            self.instructions.push(instruction);
+            return Ok(());
+        }
+
+        // The remaining operators need their operands more than once
+        let (a, b) = (self.int_eval_temp(), self.int_eval_temp());
+        self.int_compile_native(t1, ty)?;
+        if ty == Type::F64 {
+            self.instructions.push(I64ReinterpretF64);
+        }
+        self.instructions.push(SetLocal(a));
+        self.int_compile_native(t2, ty)?;
+        if ty == Type::F64 {
+            self.instructions.push(I64ReinterpretF64);
+        }
+        self.instructions.push(SetLocal(b));
+        self.int_emit_arith(op, ty, a, b);
+        Ok(())
+    }
+
+    /// Push the result of ```a op b``` for the i64 locals ```a``` and
+    /// ```b```, which hold the bits of f64 operands if ```ty``` is F64
+    fn int_emit_arith(&mut self, op: Arith, ty: Type, a: u32, b: u32) {
+        use parity_wasm::elements::Instruction::*;
+        let simple = match (op, ty) {
+            (Arith::Add, Type::I64) => Some(I64Add),
+            (Arith::Sub, Type::I64) => Some(I64Sub),
+            (Arith::Mul, Type::I64) => Some(I64Mul),
+            (Arith::Add, _) => Some(F64Add),
+            (Arith::Sub, _) => Some(F64Sub),
+            (Arith::Mul, _) => Some(F64Mul),
+            (Arith::Div, Type::F64) => Some(F64Div),
+            _ => None,
+        };
+        if let Some(instruction) = simple {
+            for local in &[a, b] {
+                self.instructions.push(GetLocal(*local));
+                if ty == Type::F64 {
+                    self.instructions.push(F64ReinterpretI64);
+                }
+            }
+            self.instructions.push(instruction);
+            return;
+        }
+
+        if ty == Type::F64 {
+            // a - b * trunc(a / b)
+            for local in &[a, b, a, b] {
+                self.instructions.push(GetLocal(*local));
+                self.instructions.push(F64ReinterpretI64);
+            }
+            self.instructions
+                .extend(vec![F64Div, F64Trunc, F64Mul, F64Sub]);
+            return;
+        }
+
+        // Integer division by zero flags an error instead of trapping,
+        // division of i64::min_value() by -1 wraps around
+        let (div, by_minus_one) = match op {
+            Arith::Div => (I64DivS, vec![I64Const(0), GetLocal(a), I64Sub]),
+            _ => (I64RemS, vec![I64Const(0)]),
+        };
+        self.instructions.extend(vec![
+            GetLocal(b),
+            I64Eqz,
+            If(BlockType::Value(ValueType::I64)),
+            I32Const(ERROR_DIVISION_BY_ZERO),
+            SetGlobal(GLOBAL_ERROR),
+            I64Const(0),
+            Else,
+            GetLocal(b),
+            I64Const(-1),
+            I64Eq,
+            If(BlockType::Value(ValueType::I64)),
+        ]);
+        self.instructions.extend(by_minus_one);
+        self.instructions
+            .extend(vec![Else, GetLocal(a), GetLocal(b), div, End, End]);
+    }
+
+    /// Compile ```t``` in boolean position, leaving its truth value on
+    /// the stack as an i32: TRUTH_TRUE, TRUTH_FALSE or TRUTH_UNKNOWN.
+    /// ```and``` and ```or``` follow three-valued logic and
+    /// short-circuit: their right operand is skipped once the left one
+    /// decides the result. Any other term is unknown if a variable it
+    /// depends on is null, and compiled by int_compile_known otherwise.
+    fn int_compile_bool(&mut self, t: &Term) -> Result<()> {
+        match t {
+            Term::Bool(bval) => {
+                let truth = if *bval { TRUTH_TRUE } else { TRUTH_FALSE };
+                self.instructions.push(Instruction::I32Const(truth));
+            }
+            Term::Not(inner) => {
+                // Swaps true and false, unknown stays unknown
+                self.instructions.push(Instruction::I32Const(TRUTH_TRUE));
+                self.int_compile_bool(inner)?;
+                self.instructions.push(Instruction::I32Sub);
+            }
+            Term::And(left, right) => {
+                // The minimum of both operands; the right operand is only
+                // evaluated if the left is not false
+                let (a, b) = (self.int_eval_temp(), self.int_eval_temp());
+                self.int_compile_bool(left)?;
+                self.instructions.extend(vec![
+                    Instruction::I64ExtendUI32,
+                    Instruction::TeeLocal(a),
+                    Instruction::I32WrapI64,
+                    Instruction::If(BlockType::Value(ValueType::I32)),
+                ]);
+                self.int_compile_bool(right)?;
+                self.int_select_truth(a, b, Instruction::I64LtU);
+                self.instructions.push(Instruction::Else);
+                self.instructions.push(Instruction::I32Const(TRUTH_FALSE));
+                self.instructions.push(Instruction::End);
+            }
+            Term::Or(left, right) => {
+                // The maximum of both operands; the right operand is only
+                // evaluated if the left is not true
+                let (a, b) = (self.int_eval_temp(), self.int_eval_temp());
+                self.int_compile_bool(left)?;
+                self.instructions.extend(vec![
+                    Instruction::I64ExtendUI32,
+                    Instruction::TeeLocal(a),
+                    Instruction::I32WrapI64,
+                    Instruction::I32Const(TRUTH_TRUE),
+                    Instruction::I32Eq,
+                    Instruction::If(BlockType::Value(ValueType::I32)),
+                    Instruction::I32Const(TRUTH_TRUE),
+                    Instruction::Else,
+                ]);
+                self.int_compile_bool(right)?;
+                self.int_select_truth(a, b, Instruction::I64GtU);
+                self.instructions.push(Instruction::End);
+            }
+            Term::IsNull(inner) => {
+                self.int_compile_is_null(inner)?;
+                self.int_truth_of_known();
+            }
+            Term::Eq(left, right) if is_truth_valued(left) && is_truth_valued(right) => {
+                // Comparing truth values is unknown if either is unknown
+                let (a, b) = (self.int_eval_temp(), self.int_eval_temp());
+                self.int_compile_bool(left)?;
+                self.instructions.push(Instruction::I64ExtendUI32);
+                self.instructions.push(Instruction::SetLocal(a));
+                self.int_compile_bool(right)?;
+                self.instructions.extend(vec![
+                    Instruction::I64ExtendUI32,
+                    Instruction::SetLocal(b),
+                    Instruction::GetLocal(a),
+                    Instruction::I64Const(TRUTH_UNKNOWN as i64),
+                    Instruction::I64Eq,
+                    Instruction::GetLocal(b),
+                    Instruction::I64Const(TRUTH_UNKNOWN as i64),
+                    Instruction::I64Eq,
+                    Instruction::I32Or,
+                    Instruction::If(BlockType::Value(ValueType::I32)),
+                    Instruction::I32Const(TRUTH_UNKNOWN),
+                    Instruction::Else,
+                    Instruction::GetLocal(a),
+                    Instruction::GetLocal(b),
+                    Instruction::I64Eq,
+                ]);
+                self.int_truth_of_known();
+                self.instructions.push(Instruction::End);
+            }
+            _ => {
+                let mut slots = Vec::new();
+                self.int_nullable_slots(t, &mut slots);
+                if slots.is_empty() {
+                    self.int_compile_known(t)?;
+                    self.int_truth_of_known();
+                } else {
+                    // Null operands are not evaluated at all, e.g. to not
+                    // divide by the zero stored for a null variable
+                    self.int_load_null_flags(&slots);
+                    self.instructions
+                        .push(Instruction::If(BlockType::Value(ValueType::I32)));
+                    self.instructions.push(Instruction::I32Const(TRUTH_UNKNOWN));
+                    self.instructions.push(Instruction::Else);
+                    self.int_compile_known(t)?;
+                    self.int_truth_of_known();
+                    self.instructions.push(Instruction::End);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Store the truth value on the stack in ```b``` and leave the one
+    /// of ```a``` and ```b``` on the stack that ```cmp``` prefers
+    fn int_select_truth(&mut self, a: u32, b: u32, cmp: Instruction) {
+        self.instructions.extend(vec![
+            Instruction::I64ExtendUI32,
+            Instruction::SetLocal(b),
+            Instruction::GetLocal(a),
+            Instruction::GetLocal(b),
+            Instruction::GetLocal(a),
+            Instruction::GetLocal(b),
+            cmp,
+            Instruction::Select,
+            Instruction::I32WrapI64,
+        ]);
+    }
+
+    /// Turn the 0 or 1 on the stack into TRUTH_FALSE or TRUTH_TRUE
+    fn int_truth_of_known(&mut self) {
+        self.instructions.push(Instruction::I32Const(1));
+        self.instructions.push(Instruction::I32Shl);
+    }
+
+    /// Compile whether ```inner``` is null, leaving 0 or 1 on the stack.
+    /// A truth value is null if it is unknown, any other value if it
+    /// depends on a variable that is null.
+    fn int_compile_is_null(&mut self, inner: &Term) -> Result<()> {
+        if is_truth_valued(inner) {
+            self.int_compile_bool(inner)?;
+            self.instructions.push(Instruction::I32Const(TRUTH_UNKNOWN));
+            self.instructions.push(Instruction::I32Eq);
+            return Ok(());
+        }
+        let mut slots = Vec::new();
+        self.int_nullable_slots(inner, &mut slots);
+        if slots.is_empty() {
+            self.instructions.push(Instruction::I32Const(0));
+        } else {
+            self.int_load_null_flags(&slots);
         }
+        Ok(())
     }
 
-    fn int_compile(&mut self, t: &Term) {
-        match &t {
-            &Term::Int(_intval) => {
-                None::<bool>.expect("Only compiled terms are supported! -- bug!");
+    /// Collect the slots of the variables ```t``` depends on. Null
+    /// tests do not depend on the values of their operands.
+    fn int_nullable_slots(&self, t: &Term, slots: &mut Vec<i64>) {
+        let slot = match t {
+            Term::Variable(var_name) => self.context.variables.get(&**var_name).cloned(),
+            Term::LoadedTerm(idx)
+                if self.lenient_slots.contains(idx)
+                    || self.context.variables.values().any(|slot| slot == idx) =>
+            {
+                Some(*idx)
             }
-            &Term::Float(_fval) => {
-                None::<bool>.expect("Only compiled terms are supported! -- bug!");
-            }
-            &Term::String(_sval) => {
-                None::<bool>.expect("Only compiled terms are supported! -- bug!");
-            }
-            &Term::Regex(_rval) => {
-                None::<bool>.expect("Only compiled terms are supported! -- bug!");
+            _ => None,
+        };
+        if let Some(slot) = slot {
+            if !slots.contains(&slot) {
+                slots.push(slot);
             }
-            &Term::Variable(_varname) => {
-                None::<bool>.expect("Only compiled terms are supported! -- bug!");
+            return;
+        }
+        match t {
+            Term::Not(inner) | Term::Neg(inner) => self.int_nullable_slots(inner, slots),
+            Term::In(subject, _) => self.int_nullable_slots(subject, slots),
+            Term::Eq(t1, t2)
+            | Term::Or(t1, t2)
+            | Term::And(t1, t2)
+            | Term::Lt(t1, t2)
+            | Term::Le(t1, t2)
+            | Term::Gt(t1, t2)
+            | Term::Ge(t1, t2)
+            | Term::Matches(t1, t2)
+            | Term::Add(t1, t2)
+            | Term::Sub(t1, t2)
+            | Term::Mul(t1, t2)
+            | Term::Div(t1, t2)
+            | Term::Mod(t1, t2) => {
+                self.int_nullable_slots(t1, slots);
+                self.int_nullable_slots(t2, slots);
             }
-            &Term::LoadedTerm(idx) => {
-                self.instructions.push(Instruction::I64Const(*idx));
+            _ => {}
+        }
+    }
+
+    /// Push whether any of the variables in ```slots``` is null, as an
+    /// i32. The null flags follow the storage of typed variables.
+    fn int_load_null_flags(&mut self, slots: &[i64]) {
+        let flags = 8 * self.context.native.len() as u32;
+        for (i, slot) in slots.iter().enumerate() {
+            self.instructions
+                .push(Instruction::GetGlobal(GLOBAL_NATIVE_BASE));
+            self.instructions
+                .push(Instruction::I32Load8U(0, flags + *slot as u32));
+            if i > 0 {
+                self.instructions.push(Instruction::I32Or);
             }
-            &Term::Not(inner) => {
-                self.instructions.push(Instruction::GetLocal(
-                    self.idx_state.expect("State should be initialized!"),
-                ));
-                self.int_compile(inner);
-                self.instructions.push(Instruction::Call(FUN_RTL_NOT));
+        }
+    }
+
+    /// Compile ```t``` in boolean position, leaving 0 or 1 on the stack,
+    /// assuming that none of its variables is null. Comparisons of
+    /// native operands are compiled to wasm instructions, everything
+    /// else is evaluated by the runtime library.
+    fn int_compile_known(&mut self, t: &Term) -> Result<()> {
+        let comparison = match t {
+            Term::Eq(left, right)
+            | Term::Lt(left, right)
+            | Term::Le(left, right)
+            | Term::Gt(left, right)
+            | Term::Ge(left, right) => {
+                match (
+                    self.context.int_native_operand(left),
+                    self.context.int_native_operand(right),
+                ) {
+                    (Some((_, Type::I64)), Some((_, Type::I64))) => Some((left, right, Type::I64)),
+                    (Some(_), Some(_)) => Some((left, right, Type::F64)),
+                    _ => None,
+                }
             }
-            &Term::And(left, right) => {
-                self.instructions.push(Instruction::GetLocal(
-                    self.idx_state.expect("State should be initialized!"),
-                ));
-                self.int_compile(left);
-                self.int_compile(right);
-                self.instructions.push(Instruction::Call(FUN_RTL_AND));
+            _ => None,
+        };
+        if let Some((left, right, ty)) = comparison {
+            self.int_compile_native(left, ty)?;
+            self.int_compile_native(right, ty)?;
+            let instruction = match (t, ty) {
+                (Term::Eq(_, _), Type::I64) => Instruction::I64Eq,
+                (Term::Lt(_, _), Type::I64) => Instruction::I64LtS,
+                (Term::Le(_, _), Type::I64) => Instruction::I64LeS,
+                (Term::Gt(_, _), Type::I64) => Instruction::I64GtS,
+                (_, Type::I64) => Instruction::I64GeS,
+                (Term::Eq(_, _), Type::F64) => Instruction::F64Eq,
+                (Term::Lt(_, _), Type::F64) => Instruction::F64Lt,
+                (Term::Le(_, _), Type::F64) => Instruction::F64Le,
+                (Term::Gt(_, _), Type::F64) => Instruction::F64Gt,
+                _ => Instruction::F64Ge,
+            };
+            self.instructions.push(instruction);
+            return Ok(());
+        }
+
+        match t {
+            Term::Variable(var_name) if self.context.int_native_operand(t).is_some() => {
+                // A natively stored variable is true unless it is zero;
+                // booleans are stored as 0 or 1
+                let (ty, cell) = self.context.native[&self.context.variables[&**var_name]];
+                self.int_load_native(ty, cell);
+                if ty.storage() == Some(Type::F64) {
+                    self.instructions
+                        .push(Instruction::F64Const(0f64.to_bits()));
+                    self.instructions.push(Instruction::F64Ne);
+                } else {
+                    self.instructions.push(Instruction::I64Const(0));
+                    self.instructions.push(Instruction::I64Ne);
+                }
             }
-            &Term::Or(left, right) => {
-                self.instructions.push(Instruction::GetLocal(
-                    self.idx_state.expect("State should be initialized!"),
-                ));
-                self.int_compile(left);
-                self.int_compile(right);
-                self.instructions.push(Instruction::Call(FUN_RTL_OR));
+            Term::Lt(_, _) | Term::Le(_, _) | Term::Gt(_, _) | Term::Ge(_, _) => {
+                self.int_compile_order(t)?
             }
-            &Term::Eq(left, right) => {
+            Term::In(subject, keys) => self.int_compile_in(subject, keys)?,
+            _ => {
                 self.instructions.push(Instruction::GetLocal(
                     self.idx_state.expect("State should be initialized!"),
                 ));
-                self.int_compile(left);
-                self.int_compile(right);
-                self.instructions.push(Instruction::Call(FUN_RTL_EQ));
-            }
-            &Term::Lt(_left, _right) => {
-                None::<bool>.expect("The < operator is not yet supported");
-            }
-            &Term::Le(_left, _right) => {
-                None::<bool>.expect("The <= operator is not yet supported");
-            }
-            &Term::Gt(_left, _right) => {
-                None::<bool>.expect("The > operator is not yet supported");
-            }
-            &Term::Ge(_left, _right) => {
-                None::<bool>.expect("The >= oeprator is not yet supported");
+                self.int_compile(t)?;
+                self.instructions.push(Instruction::Call(FUN_RTL_GET_BOOL));
             }
         }
+        Ok(())
     }
 
-    /// Compile an AST to a wasm representation that needs to be instantiated
-    /// subsequently. All variables referenced by the AST *must be* defined
-    /// by a call to define_var before ```compile``` is called.
-    pub fn compile<'b>(&'b mut self, t: &Term) -> CompiledTerm<'b, 'a> {
+    /// Compile ```t``` to a wasm module and instantiate it along with
+    /// the runtime library
+    fn int_compile_wasm(mut self, t: &Term) -> Result<CompiledTerm> {
         let mut locals = Vec::new();
         locals.push(Local::new(4, elements::ValueType::I32));
         self.idx_state = Some(0);
-        self.idx_result = Some(1);
         self.idx_mem_buf_ptr = Some(2);
         self.idx_string = Some(3);
 
         locals.push(Local::new(1, elements::ValueType::I64));
-        self.idx_var_result = Some(4);
 
+        // Allocate the storage for typed variables, followed by the null
+        // flags and the value cells of all slots. The slots are only
+        // known after loading, so the sizes are patched in below.
+        let alloc_size_at = self.instructions.len();
+        self.instructions.push(Instruction::I32Const(0));
+        self.instructions.push(Instruction::Call(FUN_ALLOC_STRING));
+        self.instructions
+            .push(Instruction::Call(FUN_GET_STRING_BUF));
+        self.instructions
+            .push(Instruction::SetGlobal(GLOBAL_NATIVE_BASE));
         self.instructions
-            .push(Instruction::I64Const(self.reserved_slots));
+            .push(Instruction::GetGlobal(GLOBAL_NATIVE_BASE));
+        let cells_at = self.instructions.len();
+        self.instructions.push(Instruction::I32Const(0));
+        self.instructions.push(Instruction::I32Add);
+        self.instructions.push(Instruction::SetGlobal(GLOBAL_CELLS));
+        // The runtime state holds every slot, including those eval
+        // allocates, so its size is patched in once eval is compiled
+        let state_size_at = self.instructions.len();
+        self.instructions.push(Instruction::I64Const(0));
         self.instructions.push(Instruction::Call(FUN_MAKE_STATE));
         self.instructions
             .push(Instruction::TeeLocal(self.idx_state.expect("pe")));
-        let t1 = self.int_build_loader(t);
+        let t1 = self.int_build_loader(t, true)?;
         self.instructions.push(Instruction::End);
-        let fun_load = self.instructions.clone();
+        let slot_count = self.locals as usize;
+        let cells = cells_offset(self.context.native.len(), slot_count);
+        self.instructions[alloc_size_at] = Instruction::I32Const((cells + 16 * slot_count) as i32);
+        self.instructions[cells_at] = Instruction::I32Const(cells as i32);
+        let mut fun_load = self.instructions.clone();
 
         self.instructions.clear();
-        self.instructions
-            .push(Instruction::GetLocal(self.idx_state.expect("pe")));
-        self.int_compile(&t1);
-        self.instructions.push(Instruction::Call(FUN_RTL_GET_BOOL));
+        self.eval_temps = 0;
+        self.int_compile_bool(&t1)?;
         self.instructions.push(Instruction::End);
         let fun_eval = self.instructions.clone();
+        fun_load[state_size_at] = Instruction::I64Const(self.locals);
 
         self.instructions.clear();
         self.instructions
@@ -442,6 +3056,15 @@ impl<'a> Context<'a> {
         let fun_cleanup = self.instructions.clone();
 
         self.instructions.clear();
+        let fun_eval_batch = build_eval_batch();
+        let fun_native_base = vec![Instruction::GetGlobal(GLOBAL_NATIVE_BASE), Instruction::End];
+        let fun_take_error = vec![
+            Instruction::GetGlobal(GLOBAL_ERROR),
+            Instruction::I32Const(0),
+            Instruction::SetGlobal(GLOBAL_ERROR),
+            Instruction::End,
+        ];
+        let fun_compare_text = build_compare_text();
 
         let mut module = builder::module();
         let make_state_sig = module.push_signature(
@@ -598,6 +3221,7 @@ impl<'a> Context<'a> {
             .i32()
             .build()
             .body()
+            .with_locals(vec![Local::new(self.eval_temps, elements::ValueType::I64)])
             .with_instructions(elements::Instructions::new(fun_eval))
             .build()
             .build()
@@ -610,20 +3234,105 @@ impl<'a> Context<'a> {
             .with_instructions(elements::Instructions::new(fun_cleanup))
             .build()
             .build()
+            .function()
+            .signature()
+            .param()
+            .i32()
+            .param()
+            .i32()
+            .param()
+            .i32()
+            .param()
+            .i32()
+            .param()
+            .i32()
+            .return_type()
+            .i32()
+            .build()
+            .body()
+            .with_locals(vec![Local::new(9, elements::ValueType::I32)])
+            .with_instructions(elements::Instructions::new(fun_eval_batch))
+            .build()
+            .build()
+            .function()
+            .signature()
+            .return_type()
+            .i32()
+            .build()
+            .body()
+            .with_instructions(elements::Instructions::new(fun_native_base))
+            .build()
+            .build()
+            .function()
+            .signature()
+            .return_type()
+            .i32()
+            .build()
+            .body()
+            .with_instructions(elements::Instructions::new(fun_take_error))
+            .build()
+            .build()
+            .function()
+            .signature()
+            .param()
+            .i64()
+            .param()
+            .i64()
+            .return_type()
+            .i32()
+            .build()
+            .body()
+            .with_locals(vec![Local::new(7, elements::ValueType::I32)])
+            .with_instructions(elements::Instructions::new(fun_compare_text))
+            .build()
+            .build()
+            .global()
+            .value_type()
+            .i32()
+            .mutable()
+            .init_expr(Instruction::I32Const(0))
+            .build()
+            .global()
+            .value_type()
+            .i32()
+            .mutable()
+            .init_expr(Instruction::I32Const(0))
+            .build()
+            .global()
+            .value_type()
+            .i32()
+            .mutable()
+            .init_expr(Instruction::I32Const(0))
+            .build()
             .export()
             .field("load")
             .internal()
-            .func(13)
+            .func(NUM_IMPORTED_FUNS)
             .build()
             .export()
             .field("eval")
             .internal()
-            .func(14)
+            .func(FUN_EVAL)
             .build()
             .export()
             .field("cleanup")
             .internal()
-            .func(15)
+            .func(NUM_IMPORTED_FUNS + 2)
+            .build()
+            .export()
+            .field("eval_batch")
+            .internal()
+            .func(NUM_IMPORTED_FUNS + 3)
+            .build()
+            .export()
+            .field("native_base")
+            .internal()
+            .func(NUM_IMPORTED_FUNS + 4)
+            .build()
+            .export()
+            .field("take_error")
+            .internal()
+            .func(NUM_IMPORTED_FUNS + 5)
             .build()
             .import() // 0
             .module("wormrtl")
@@ -712,111 +3421,679 @@ impl<'a> Context<'a> {
             .build();
 
         let mut v = Vec::new();
-        module.serialize(&mut v).unwrap();
+        module
+            .serialize(&mut v)
+            .map_err(compile_error("serialize formula.wasm"))?;
 
-        {
-            let mut f = std::fs::File::create("/tmp/test.wasm").expect("Test");
-            f.write_all(v.as_slice()).expect("write to file");
+        if let Some(dump_dir) = &self.context.dump_dir {
+            let path = dump_dir.join(format!("formula-{:016x}.wasm", fnv1a(&v)));
+            std::fs::File::create(&path)
+                .and_then(|mut f| f.write_all(v.as_slice()))
+                .map_err(|e| Error::Io(format!("{}: {}", path.display(), e)))?;
+        }
+
+        let rtl_module = self
+            .context
+            .rtl_module
+            .clone()
+            .ok_or_else(|| Error::WasmCompile("no runtime library".to_string()))?;
+        let formula_module =
+            wasmer_runtime::compile(v.as_slice()).map_err(compile_error("formula.wasm"))?;
+
+        Ok(CompiledTerm {
+            backend: CompiledBackend::Wasm(Arc::new(CompiledWasm {
+                rtl_module,
+                formula_module,
+                wasm: v,
+                native: self.context.native.clone(),
+                null_slots: self
+                    .context
+                    .variables
+                    .values()
+                    .chain(self.lenient_slots.iter())
+                    .cloned()
+                    .collect(),
+                slot_count,
+                dynamic_slots: self.dynamic_slots,
+            })),
+            variables: self.context.int_variable_slots(),
+        })
+    }
+}
+
+impl CompiledTerm {
+    /// The serialized wasm module the formula was compiled to, e.g.
+    /// for inspection with external wasm tooling. None if the
+    /// formula was compiled for the interpreter backend.
+    pub fn wasm_bytes(&self) -> Option<&[u8]> {
+        match &self.backend {
+            CompiledBackend::Wasm(cw) => Some(&cw.wasm),
+            CompiledBackend::Interpreter(_) => None,
         }
+    }
+
+    /// Instantiate a compiled term to be used subsequently for
+    /// evaluation
+    pub fn instantiate(&self) -> Result<InstantiatedTerm> {
+        let evaluator: Box<dyn Evaluator> = match &self.backend {
+            CompiledBackend::Wasm(cw) => Box::new(WasmEvaluator::new(cw.clone())?),
+            CompiledBackend::Interpreter(program) => Box::new(Interpreter::new(program.clone())),
+        };
+        Ok(InstantiatedTerm {
+            variables: self.variables.clone(),
+            evaluator,
+        })
+    }
+}
+
+/// The formula's ```eval_batch(state, columns, column_count, rows, out)```
+type EvalBatchFunc<'a> = wasmer_runtime::Func<'a, (i32, i32, i32, i32, i32), i32>;
+
+impl WasmEvaluator {
+    /// wormrtl's ```make_i64``` function
+    fn make_i64_call(&self) -> Result<wasmer_runtime::Func<'_, (i32, i64, i64), i64>> {
+        self.rtl_instance
+            .func("make_i64")
+            .map_err(instantiate_error("make_i64"))
+    }
+
+    /// wormrtl's ```make_f64``` function
+    fn make_f64_call(&self) -> Result<wasmer_runtime::Func<'_, (i32, i64, f64), i64>> {
+        self.rtl_instance
+            .func("make_f64")
+            .map_err(instantiate_error("make_f64"))
+    }
+
+    /// wormrtl's ```alloc_string``` function
+    fn alloc_string_call(&self) -> Result<wasmer_runtime::Func<'_, i32, i32>> {
+        self.rtl_instance
+            .func("alloc_string")
+            .map_err(instantiate_error("alloc_string"))
+    }
+
+    /// wormrtl's ```get_string_buf``` function
+    fn get_string_buf_call(&self) -> Result<wasmer_runtime::Func<'_, i32, i32>> {
+        self.rtl_instance
+            .func("get_string_buf")
+            .map_err(instantiate_error("get_string_buf"))
+    }
+
+    /// wormrtl's ```make_string``` function
+    fn make_string_call(&self) -> Result<wasmer_runtime::Func<'_, (i32, i64, i32, i32), i64>> {
+        self.rtl_instance
+            .func("make_string")
+            .map_err(instantiate_error("make_string"))
+    }
+
+    /// The formula's ```eval``` function
+    fn eval_call(&self) -> Result<wasmer_runtime::Func<'_, i32, i32>> {
+        self.formula_instance
+            .func("eval")
+            .map_err(instantiate_error("eval"))
+    }
+
+    /// The formula's ```eval_batch``` function
+    fn eval_batch_call(&self) -> Result<EvalBatchFunc<'_>> {
+        self.formula_instance
+            .func("eval_batch")
+            .map_err(instantiate_error("eval_batch"))
+    }
+
+    /// The formula's ```take_error``` function
+    fn take_error_call(&self) -> Result<wasmer_runtime::Func<'_, (), i32>> {
+        self.formula_instance
+            .func("take_error")
+            .map_err(instantiate_error("take_error"))
+    }
+}
 
+impl WasmEvaluator {
+    fn new(compiled: Arc<CompiledWasm>) -> Result<WasmEvaluator> {
         let import_object = imports! {
             "env" => {
                 "print_str" => func!(print_str),
             },
         };
-        let rtl_module_instance = Rc::new(
-            self.rtl_module
+        let rtl_instance = Rc::new(
+            compiled
+                .rtl_module
                 .instantiate(&import_object)
-                .expect("wormrtl.wasm instance"),
+                .map_err(instantiate_error("wormrtl.wasm"))?,
         );
-
-        let formula_module = wasmer_runtime::compile(v.as_slice()).expect("formula.wasm module");
         let mut fm_import_object: wasmer_runtime::ImportObject = imports! {
             "env" => {
                 "print_str" => func!(print_str),
             },
-            // "wormrtl" => rtl_module_instance,
         };
-        fm_import_object.register("wormrtl", rtl_module_instance.clone());
-        let fm_instance = formula_module
+        fm_import_object.register("wormrtl", rtl_instance.clone());
+        let formula_instance = compiled
+            .formula_module
             .instantiate(&fm_import_object)
-            .expect("formula.wasm instance");
-        let fm_init: wasmer_runtime::Func<(), (i32)> = fm_instance.func("load").expect("load");
-        let fm_init_res = fm_init.call().expect("fm_init");
+            .map_err(instantiate_error("formula.wasm"))?;
+        let load: wasmer_runtime::Func<(), i32> = formula_instance
+            .func("load")
+            .map_err(instantiate_error("load"))?;
+        let state = load.call().map_err(trap("load"))?;
+        let native_base_call: wasmer_runtime::Func<(), i32> = formula_instance
+            .func("native_base")
+            .map_err(instantiate_error("native_base"))?;
+        let native_base = native_base_call.call().map_err(trap("native_base"))? as usize;
 
-        CompiledTerm {
-            rtl_module_instance,
-            fm_import_object,
-            formula_module_instance: fm_instance,
-            fm_init_res,
-            context: self,
+        let memory = rtl_instance.context().memory(0).clone();
+
+        // Variables are null until they are set
+        let null_flags = native_base + 8 * compiled.native.len();
+        let view = memory.view::<u8>();
+        for slot in 0..compiled.slot_count {
+            view[null_flags + slot].set(0);
+        }
+        for slot in &compiled.null_slots {
+            view[null_flags + *slot as usize].set(1);
         }
+        // The value cells of literals have been written by load
+        let cells = native_base + cells_offset(compiled.native.len(), compiled.slot_count);
+        for slot in &compiled.dynamic_slots {
+            for i in 0..16 {
+                view[cells + *slot as usize * 16 + i].set(0);
+            }
+        }
+
+        Ok(WasmEvaluator {
+            compiled,
+            rtl_instance,
+            formula_instance,
+            state,
+            memory,
+            batch_buffer: RefCell::new(None),
+            native_base,
+            null_flags,
+            cells,
+            shadows: RefCell::new(HashMap::new()),
+        })
     }
 }
 
-struct CompilationContext {
-    mem: Vec<u8>,
-    mem_ptr: usize,
-}
+impl Evaluator for WasmEvaluator {
+    fn set_string(&self, slot: i64, string: &str) -> Result<()> {
+        self.write_string(slot, string.as_bytes(), Type::String)
+    }
 
-impl<'a, 'b> CompiledTerm<'a, 'b> {
-    /// Instantiate a compiled term to be used subsequently for
-    /// evaluation
-    pub fn instantiate(&'b self) -> InstantiatedTerm<'a, 'b> {
-        let make_i64_call = self.rtl_module_instance.func("make_i64").expect("make_i64");
+    fn set_bytes(&self, slot: i64, bytes: &[u8]) -> Result<()> {
+        self.write_string(slot, bytes, Type::Bytes)
+    }
+    fn set_i64(&self, slot: i64, intval: i64) -> Result<()> {
+        if let Some((ty, cell)) = self.compiled.native.get(&slot) {
+            check_type(ty.storage(), Type::I64)?;
+            self.write_native(*cell, intval.to_le_bytes());
+            self.set_null_flag(slot, false);
+            return Ok(());
+        }
+        self.make_i64_call()?
+            .call(self.state, slot, intval)
+            .map_err(trap("make_i64"))?;
+        self.set_null_flag(slot, false);
+        self.write_cell(slot, VALUE_I64, intval);
+        Ok(())
+    }
 
-        let make_f64_call = self.rtl_module_instance.func("make_f64").expect("make_f64");
+    fn set_f64(&self, slot: i64, fval: f64) -> Result<()> {
+        if let Some((ty, cell)) = self.compiled.native.get(&slot) {
+            check_type(ty.storage(), Type::F64)?;
+            self.write_native(*cell, fval.to_bits().to_le_bytes());
+            self.set_null_flag(slot, false);
+            return Ok(());
+        }
+        self.make_f64_call()?
+            .call(self.state, slot, fval)
+            .map_err(trap("make_f64"))?;
+        self.set_null_flag(slot, false);
+        self.write_cell(slot, VALUE_F64, fval.to_bits() as i64);
+        Ok(())
+    }
 
-        let alloc_string_call = self
-            .rtl_module_instance
-            .func("alloc_string")
-            .expect("alloc_string");
+    fn set_bool(&self, slot: i64, bval: bool) -> Result<()> {
+        match self.compiled.native.get(&slot) {
+            Some((ty, cell)) => {
+                check_type(Some(*ty), Type::Bool)?;
+                self.write_native(*cell, (bval as i64).to_le_bytes());
+                self.set_null_flag(slot, false);
+                Ok(())
+            }
+            // The runtime library has no constructor for booleans, so
+            // untyped variables store them as 0 or 1
+            None => self.set_i64(slot, bval as i64),
+        }
+    }
 
-        let get_string_buf_call = self
-            .rtl_module_instance
-            .func("get_string_buf")
-            .expect("get_string_buf");
+    fn set_null(&self, slot: i64) -> Result<()> {
+        self.set_null_flag(slot, true);
+        Ok(())
+    }
 
-        let make_string_call = self
-            .rtl_module_instance
-            .func("make_string")
-            .expect("make_string");
+    fn run(&self) -> Result<Option<bool>> {
+        let res = self.eval_call()?.call(self.state).map_err(trap("eval"))?;
+        self.take_error()?;
+        if res == TRUTH_UNKNOWN {
+            Ok(None)
+        } else {
+            Ok(Some(res == TRUTH_TRUE))
+        }
+    }
 
-        let eval_call = self.formula_module_instance.func("eval").expect("eval");
+    /// Copies all columns into linear memory and evaluates all rows
+    /// with a single call to the formula's eval_batch function.
+    fn run_batch(
+        &self,
+        columns: &[(i64, Option<Type>, &Column)],
+        rows: usize,
+    ) -> Result<Vec<bool>> {
+        // Lay out descriptors, column data, null masks and the result
+        // bitmap
+        let mut offset = align8(columns.len() * COLUMN_DESC_SIZE);
+        let mut data_offsets = Vec::with_capacity(columns.len());
+        for (_, _, column) in columns {
+            let (values, nulls) = column.split();
+            data_offsets.push(offset);
+            offset += rows * 8;
+            if column_kind(values) == COLUMN_STRING {
+                offset += align8(
+                    (0..rows)
+                        .filter_map(|row| values.text(row))
+                        .map(|v| v.len())
+                        .sum(),
+                );
+            }
+            if nulls.is_some() {
+                offset += align8(rows);
+            }
+        }
+        let out_offset = offset;
+        let size = out_offset + (rows + 7) / 8;
+        if size > i32::max_value() as usize {
+            return Err(Error::InvalidInput(format!(
+                "batch of {} bytes is too large",
+                size
+            )));
+        }
 
-        let memory = self.rtl_module_instance.context().memory(0);
+        let base = self.batch_buffer(size)?;
+        let view = self.memory.view::<u8>();
+        let write = |at: usize, bytes: &[u8]| {
+            for (i, b) in bytes.iter().enumerate() {
+                view[base + at + i].set(*b);
+            }
+        };
+        for (i, ((slot, _, column), data_offset)) in
+            columns.iter().zip(data_offsets.iter()).enumerate()
+        {
+            let (values, nulls) = column.split();
+            let kind = column_kind(values);
+            let desc = i * COLUMN_DESC_SIZE;
+            match self.compiled.native.get(slot) {
+                Some((_, cell)) => {
+                    // Typed variables are copied straight to their cell
+                    let address = (self.native_base + *cell as usize * 8) as i64;
+                    write(desc, &address.to_le_bytes());
+                    write(desc + 8, &COLUMN_NATIVE.to_le_bytes());
+                }
+                None => {
+                    write(desc, &slot.to_le_bytes());
+                    write(desc + 8, &kind.to_le_bytes());
+                }
+            }
+            write(desc + 12, &((base + data_offset) as i32).to_le_bytes());
+            write(
+                desc + 16,
+                &((self.cells + *slot as usize * 16) as i32).to_le_bytes(),
+            );
+            write(desc + 20, &column_value(kind).to_le_bytes());
+            let strings = data_offset + rows * 8;
+            let mut end = strings;
+            if kind == COLUMN_STRING {
+                for row in 0..rows {
+                    let text = values.text(row).unwrap_or(&[]);
+                    let entry = data_offset + row * 8;
+                    write(entry, &((base + end) as i32).to_le_bytes());
+                    write(entry + 4, &(text.len() as i32).to_le_bytes());
+                    write(end, text);
+                    end += text.len();
+                }
+                end = strings + align8(end - strings);
+            } else {
+                for row in 0..rows {
+                    write(data_offset + row * 8, &values.word(row)?.to_le_bytes());
+                }
+            }
+            match nulls {
+                Some(nulls) => {
+                    for (row, null) in nulls.iter().enumerate() {
+                        write(end + row, &[*null as u8]);
+                    }
+                    write(desc + 24, &((base + end) as i32).to_le_bytes());
+                    write(
+                        desc + 28,
+                        &((self.null_flags + *slot as usize) as i32).to_le_bytes(),
+                    );
+                }
+                None => write(desc + 24, &0i32.to_le_bytes()),
+            }
+        }
+        for i in out_offset..size {
+            view[base + i].set(0);
+        }
+        for (slot, _, _) in columns {
+            self.set_null_flag(*slot, false);
+        }
 
-        InstantiatedTerm {
-            make_i64_call,
-            make_f64_call,
-            alloc_string_call,
-            get_string_buf_call,
-            make_string_call,
-            eval_call,
-            memory,
-            ct: self,
+        self.eval_batch_call()?
+            .call(
+                self.state,
+                base as i32,
+                columns.len() as i32,
+                rows as i32,
+                (base + out_offset) as i32,
+            )
+            .map_err(trap("eval_batch"))?;
+        self.take_error()?;
+
+        // The value cells of string columns point into the batch
+        // buffer, which the next batch overwrites, so they get their
+        // own copy of the last row
+        for (slot, _, column) in columns {
+            let (values, _) = column.split();
+            if let Some(last) = rows.checked_sub(1).and_then(|row| values.text(row)) {
+                self.write_text_cell(*slot, last)?;
+            }
+        }
+
+        // The call may have grown the memory, so get a fresh view
+        let view = self.memory.view::<u8>();
+        Ok((0..rows)
+            .map(|row| view[base + out_offset + row / 8].get() & (1 << (row % 8)) != 0)
+            .collect())
+    }
+}
+
+impl WasmEvaluator {
+    /// Fail if the last evaluation flagged an error, and reset the flag
+    fn take_error(&self) -> Result<()> {
+        match self.take_error_call()?.call().map_err(trap("take_error"))? {
+            0 => Ok(()),
+            code if code == ERROR_DIVISION_BY_ZERO => Err(Error::DivisionByZero),
+            code if code == ERROR_NOT_A_NUMBER => Err(Error::TypeMismatch(
+                "arithmetic on a value that is not a number".to_string(),
+            )),
+            code => Err(Error::Trap(format!("unknown error code {}", code))),
+        }
+    }
+
+    /// Store ```string_bytes``` as a runtime string value in ```slot```;
+    /// ```given``` is the type of the value, for checking it against
+    /// natively stored typed variables
+    fn write_string(&self, slot: i64, string_bytes: &[u8], given: Type) -> Result<()> {
+        if let Some((ty, _)) = self.compiled.native.get(&slot) {
+            check_type(Some(*ty), given)?;
+        }
+        if string_bytes.len() > i32::max_value() as usize {
+            return Err(Error::InvalidInput(format!(
+                "string of {} bytes is too long",
+                string_bytes.len()
+            )));
+        }
+        let alloc_buffer = self
+            .alloc_string_call()?
+            .call(string_bytes.len() as i32)
+            .map_err(trap("alloc_string"))?;
+        let ptr = self
+            .get_string_buf_call()?
+            .call(alloc_buffer)
+            .map_err(trap("get_string_buf"))? as usize;
+        let view = self.memory.view::<u8>();
+        for i in 0..string_bytes.len() {
+            view[ptr + i].set(string_bytes[i]);
+        }
+        self.make_string_call()?
+            .call(self.state, slot, alloc_buffer, string_bytes.len() as i32)
+            .map_err(trap("make_string"))?;
+        self.set_null_flag(slot, false);
+        self.write_text_cell(slot, string_bytes)?;
+        Ok(())
+    }
+
+    /// Mark the variable in ```slot``` as null or set
+    fn set_null_flag(&self, slot: i64, null: bool) {
+        if slot >= 0 && (slot as usize) < self.compiled.slot_count {
+            self.memory.view::<u8>()[self.null_flags + slot as usize].set(null as u8);
+        }
+    }
+
+    /// Store the value of a typed variable
+    fn write_native(&self, cell: u32, bytes: [u8; 8]) {
+        let view = self.memory.view::<u8>();
+        let at = self.native_base + cell as usize * 8;
+        for (i, b) in bytes.iter().enumerate() {
+            view[at + i].set(*b);
+        }
+    }
+
+    /// Address of a buffer in the runtime's memory of at least
+    /// ```size``` bytes. The buffer is reused by subsequent batches.
+    fn batch_buffer(&self, size: usize) -> Result<usize> {
+        let mut batch_buffer = self.batch_buffer.borrow_mut();
+        match *batch_buffer {
+            Some((ptr, capacity)) if capacity >= size => Ok(ptr),
+            _ => {
+                let capacity = size.max(4096);
+                let handle = self
+                    .alloc_string_call()?
+                    .call(capacity as i32)
+                    .map_err(trap("alloc_string"))?;
+                let ptr = self
+                    .get_string_buf_call()?
+                    .call(handle)
+                    .map_err(trap("get_string_buf"))? as usize;
+                *batch_buffer = Some((ptr, capacity));
+                Ok(ptr)
+            }
+        }
+    }
+
+    /// Store ```tag``` and ```payload``` in the value cell of
+    /// ```slot```, if the formula reads it
+    fn write_cell(&self, slot: i64, tag: i32, payload: i64) {
+        if !self.compiled.dynamic_slots.contains(&slot) {
+            return;
+        }
+        let view = self.memory.view::<u8>();
+        let at = self.cells + slot as usize * 16;
+        for (i, b) in tag.to_le_bytes().iter().enumerate() {
+            view[at + i].set(*b);
         }
+        for (i, b) in payload.to_le_bytes().iter().enumerate() {
+            view[at + 8 + i].set(*b);
+        }
+    }
+
+    /// Copy ```text``` to the buffer of ```slot``` and point its value
+    /// cell to it, if the formula reads it. The buffer grows as needed.
+    fn write_text_cell(&self, slot: i64, text: &[u8]) -> Result<()> {
+        if !self.compiled.dynamic_slots.contains(&slot) {
+            return Ok(());
+        }
+        let mut shadows = self.shadows.borrow_mut();
+        let address = match shadows.get(&slot) {
+            Some((address, capacity)) if *capacity >= text.len() => *address,
+            previous => {
+                let capacity = text
+                    .len()
+                    .max(previous.map(|(_, capacity)| 2 * capacity).unwrap_or(16));
+                let handle = self
+                    .alloc_string_call()?
+                    .call(capacity as i32)
+                    .map_err(trap("alloc_string"))?;
+                let address = self
+                    .get_string_buf_call()?
+                    .call(handle)
+                    .map_err(trap("get_string_buf"))? as usize;
+                shadows.insert(slot, (address, capacity));
+                address
+            }
+        };
+        let view = self.memory.view::<u8>();
+        for (i, b) in text.iter().enumerate() {
+            view[address + i].set(*b);
+        }
+        self.write_cell(slot, VALUE_TEXT, address as i64 | (text.len() as i64) << 32);
+        Ok(())
     }
 }
 
-impl<'a, 'b> InstantiatedTerm<'a, 'b> {
-    /// Evaluate the formula
-    pub fn run(&self) -> bool {
-        let res = self.eval_call.call(self.ct.fm_init_res).expect("eval_call");
-        res != 0
+impl InstantiatedTerm {
+    /// Evaluate the formula. An unknown result, e.g. of ```x > 1```
+    /// when ```x``` is null, counts as false, just like a row is only
+    /// selected by a SQL WHERE clause if its condition is true. Use
+    /// ```evaluate``` to tell unknown and false apart.
+    pub fn run(&self) -> Result<bool> {
+        Ok(self.evaluator.run()? == Some(true))
+    }
+
+    /// Evaluate the formula with three-valued logic: None if the
+    /// result is unknown because it depends on null values.
+    pub fn evaluate(&self) -> Result<Option<bool>> {
+        self.evaluator.run()
+    }
+
+    /// Evaluate the formula for many rows with a single call into
+    /// webassembly. ```columns``` holds one column of values per
+    /// variable; all columns must have the same number of rows. The
+    /// type of a column must match the declared type of its variable.
+    /// A column wrapped in ```Column::Nullable``` leaves the variable
+    /// null in the rows its mask marks, which must have one entry per
+    /// row. Variables not mentioned in ```columns``` keep their current
+    /// value, the others keep the value of the last row. Returns the
+    /// formula's result for every row, counting unknown as false.
+    pub fn run_batch(&self, columns: &[(&str, Column)]) -> Result<Vec<bool>> {
+        let rows = columns.first().map(|(_, c)| c.len()).unwrap_or(0);
+        let mut slots = Vec::with_capacity(columns.len());
+        for (name, column) in columns {
+            match self.variables.slots.get(*name) {
+                Some(slot) => {
+                    let declared = self.variables.types.get(slot).cloned();
+                    check_type(declared, column_type(column))?;
+                    slots.push((*slot, declared, column))
+                }
+                None => {
+                    return Err(Error::UnknownVariable(vec![UnknownIdentifier {
+                        name: name.to_string(),
+                        offset: None,
+                    }]))
+                }
+            }
+            if column.len() != rows {
+                return Err(Error::InvalidInput(format!(
+                    "column {} has {} rows, expected {}",
+                    name,
+                    column.len(),
+                    rows
+                )));
+            }
+            match column.split() {
+                (Column::Nullable(_, _), _) => return Err(nested_nulls()),
+                (_, Some(nulls)) if nulls.len() != rows => {
+                    return Err(Error::InvalidInput(format!(
+                        "null mask of column {} has {} rows, expected {}",
+                        name,
+                        nulls.len(),
+                        rows
+                    )))
+                }
+                _ => {}
+            }
+        }
+        self.evaluator.run_batch(&slots, rows)
+    }
+
+    /// Assign every variable declared in the context the value found
+    /// in ```record``` at the path it is named by, e.g. the field
+    /// ```status``` of the record in the field ```http``` for the
+    /// variable ```http.status```. Integers are accepted for timestamp
+    /// variables as nanoseconds since the unix epoch. Variables are set
+    /// to null where the record has no value or ```Value::Null```.
+    /// Fails with ```Error::TypeMismatch``` if the value is a list or
+    /// record.
+    pub fn set_record(&self, record: &Value) -> Result<()> {
+        for (name, slot) in &self.variables.slots {
+            let mut var = Variable {
+                idx: *slot,
+                ty: self.variables.types.get(slot).cloned(),
+                ct: self,
+            };
+            match record.get(name) {
+                Some(Value::Bool(bval)) => var.set_bool(*bval)?,
+                Some(Value::I64(intval)) if var.ty == Some(Type::Timestamp) => {
+                    self.evaluator.set_i64(*slot, *intval)?
+                }
+                Some(Value::I64(intval)) => var.set_i64(*intval)?,
+                Some(Value::F64(fval)) => var.set_f64(*fval)?,
+                Some(Value::String(string)) => var.set_string(string)?,
+                Some(Value::Bytes(bytes)) => var.set_bytes(bytes)?,
+                Some(Value::List(_)) | Some(Value::Record(_)) => {
+                    return Err(Error::TypeMismatch(format!(
+                        "{} is not a single value",
+                        name
+                    )))
+                }
+                Some(Value::Null) | None => var.set_null()?,
+            }
+        }
+        Ok(())
     }
 
     /// Retrieve a reference to a variable defined in the context that
     /// created this instance. Subsequent access to the variable should be
-    /// O(1).
-    pub fn get_variable(&self, var_name: &str) -> Option<Variable> {
-        if let Some(var_num) = self.ct.context.variables.get(var_name) {
-            Some(Variable {
+    /// O(1). Variables declared with ```define_var_typed``` must be
+    /// retrieved with ```get_typed_variable``` instead, which fails
+    /// with ```Error::TypeMismatch``` here.
+    pub fn get_variable(&self, var_name: &str) -> Result<Variable> {
+        let var = self.int_get_variable(var_name)?;
+        if let Some(ty) = var.ty {
+            return Err(Error::TypeMismatch(format!(
+                "{} is declared as {:?}, use get_typed_variable",
+                var_name, ty
+            )));
+        }
+        Ok(var)
+    }
+
+    /// Like ```get_variable```, but for a variable declared with
+    /// type ```T::TYPE```. The returned handle only accepts values
+    /// of that type.
+    pub fn get_typed_variable<T: VarValue>(&self, var_name: &str) -> Result<TypedVariable<T>> {
+        let var = self.int_get_variable(var_name)?;
+        if var.ty != Some(T::TYPE) {
+            return Err(Error::TypeMismatch(format!(
+                "{} is not declared as {:?}",
+                var_name,
+                T::TYPE
+            )));
+        }
+        Ok(TypedVariable {
+            var,
+            marker: PhantomData,
+        })
+    }
+
+    fn int_get_variable(&self, var_name: &str) -> Result<Variable> {
+        match self.variables.slots.get(var_name) {
+            Some(var_num) => Ok(Variable {
                 idx: *var_num,
+                ty: self.variables.types.get(var_num).cloned(),
                 ct: self,
-            })
-        } else {
-            None
+            }),
+            None => Err(Error::UnknownVariable(vec![UnknownIdentifier {
+                name: var_name.to_string(),
+                offset: None,
+            }])),
         }
     }
 }
@@ -0,0 +1,434 @@
+//! A tree-walking interpreter with the same semantics as the wasm
+//! backend. It has no startup cost, which makes it preferable for
+//! formulas that are evaluated only a few times, and it serves as a
+//! reference to cross-check the wasm backend against.
+use regex::Regex;
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use crate::error::{Error, Result};
+use crate::evaluator::Evaluator;
+use crate::term::*;
+
+/// A runtime value
+#[derive(Debug, Clone)]
+enum Value {
+    Null,
+    Bool(bool),
+    I64(i64),
+    F64(f64),
+    String(Arc<str>),
+    Bytes(Arc<[u8]>),
+    Regex(Arc<Regex>),
+}
+
+/// A formula lowered for interpretation. Variables are resolved to
+/// slots and literals are converted to values ahead of time.
+#[derive(Debug)]
+enum Node {
+    Const(Value),
+    Slot(usize),
+    /// The truthiness of a bare variable in boolean position
+    Truthy(Box<Node>),
+    Not(Box<Node>),
+    Eq(Box<Node>, Box<Node>),
+    Or(Box<Node>, Box<Node>),
+    And(Box<Node>, Box<Node>),
+    Lt(Box<Node>, Box<Node>),
+    Le(Box<Node>, Box<Node>),
+    Gt(Box<Node>, Box<Node>),
+    Ge(Box<Node>, Box<Node>),
+    Matches(Box<Node>, Box<Node>),
+    In(Box<Node>, ValueSet),
+    IsNull(Box<Node>),
+    Arith(Arith, Box<Node>, Box<Node>),
+    Neg(Box<Node>),
+}
+
+/// The literals of an ```in``` list, hashed (or sorted, for floats)
+/// by type
+#[derive(Debug, Default)]
+struct ValueSet {
+    bools: [bool; 2],
+    ints: HashSet<i64>,
+    floats: Vec<f64>,
+    texts: HashSet<Vec<u8>>,
+}
+
+/// Magnitude from which not every integer is exactly representable
+/// as a f64
+const F64_EXACT: f64 = 9_007_199_254_740_992.0;
+
+impl ValueSet {
+    fn new(list: &[Term]) -> Result<ValueSet> {
+        let mut set = ValueSet::default();
+        for element in list {
+            match element {
+                Term::Bool(bval) => set.bools[*bval as usize] = true,
+                Term::Int(intval) => {
+                    set.ints.insert(*intval);
+                }
+                Term::Float(fval) => set.floats.push(*fval),
+                Term::String(sval) => {
+                    set.texts.insert(sval.as_bytes().to_vec());
+                }
+                other => {
+                    return Err(Error::UnsupportedOperator(format!(
+                        "in needs a list of literals, got {}",
+                        other
+                    )))
+                }
+            }
+        }
+        set.floats.retain(|f| !f.is_nan());
+        set.floats.sort_by(|l, r| l.partial_cmp(r).expect("no NaN"));
+        Ok(set)
+    }
+
+    /// Whether ```value``` equals one of the literals, by the rules
+    /// of ```equals```
+    fn contains(&self, value: &Value) -> bool {
+        let has_float = |fval: f64| {
+            self.floats
+                .binary_search_by(|f| f.partial_cmp(&fval).expect("no NaN"))
+                .is_ok()
+        };
+        match value {
+            Value::Null | Value::Regex(_) => false,
+            Value::Bool(bval) => self.bools[*bval as usize],
+            Value::I64(intval) => self.ints.contains(intval) || has_float(*intval as f64),
+            Value::F64(fval) if fval.is_nan() => false,
+            Value::F64(fval) => {
+                has_float(*fval)
+                    || if fval.abs() < F64_EXACT {
+                        fval.fract() == 0.0 && self.ints.contains(&(*fval as i64))
+                    } else {
+                        self.ints.iter().any(|intval| *intval as f64 == *fval)
+                    }
+            }
+            Value::String(s) => self.texts.contains(s.as_bytes()),
+            Value::Bytes(b) => self.texts.contains(&b[..]),
+        }
+    }
+}
+
+/// A compiled formula that can be instantiated any number of times
+#[derive(Debug)]
+pub struct Program {
+    root: Node,
+}
+
+impl Program {
+    /// Lower ```t```, using ```resolve``` to look up the slot of
+    /// every variable it references.
+    pub fn new(t: &Term, resolve: &mut dyn FnMut(&str) -> Result<i64>) -> Result<Program> {
+        Ok(Program {
+            root: lower_bool(t, resolve)?,
+        })
+    }
+}
+
+/// Lower ```t```, which is in boolean position
+fn lower_bool(t: &Term, resolve: &mut dyn FnMut(&str) -> Result<i64>) -> Result<Node> {
+    match t {
+        Term::Variable(_) => Ok(Node::Truthy(Box::new(lower(t, resolve)?))),
+        _ => lower(t, resolve),
+    }
+}
+
+fn lower(t: &Term, resolve: &mut dyn FnMut(&str) -> Result<i64>) -> Result<Node> {
+    match t {
+        Term::Bool(bval) => Ok(Node::Const(Value::Bool(*bval))),
+        Term::Int(intval) => Ok(Node::Const(Value::I64(*intval))),
+        Term::Float(fval) => Ok(Node::Const(Value::F64(*fval))),
+        Term::String(sval) => Ok(Node::Const(Value::String(Arc::from(&**sval)))),
+        Term::Regex(rval, flags) => Ok(Node::Const(Value::Regex(Arc::new(flags.build(rval)?)))),
+        Term::Variable(var_name) => Ok(Node::Slot(resolve(var_name)? as usize)),
+        Term::LoadedTerm(_) => Err(Error::UnsupportedOperator(
+            "cannot interpret a loaded term".to_string(),
+        )),
+        Term::Not(inner) => Ok(Node::Not(Box::new(lower_bool(inner, resolve)?))),
+        Term::Eq(t1, t2) => lower_binary(Node::Eq, t1, t2, resolve),
+        Term::Or(t1, t2) => Ok(Node::Or(
+            Box::new(lower_bool(t1, resolve)?),
+            Box::new(lower_bool(t2, resolve)?),
+        )),
+        Term::And(t1, t2) => Ok(Node::And(
+            Box::new(lower_bool(t1, resolve)?),
+            Box::new(lower_bool(t2, resolve)?),
+        )),
+        Term::Lt(t1, t2) => lower_binary(Node::Lt, t1, t2, resolve),
+        Term::Le(t1, t2) => lower_binary(Node::Le, t1, t2, resolve),
+        Term::Gt(t1, t2) => lower_binary(Node::Gt, t1, t2, resolve),
+        Term::Ge(t1, t2) => lower_binary(Node::Ge, t1, t2, resolve),
+        Term::Matches(t1, t2) => lower_binary(Node::Matches, t1, t2, resolve),
+        Term::In(subject, list) => Ok(Node::In(
+            Box::new(lower(subject, resolve)?),
+            ValueSet::new(list)?,
+        )),
+        Term::IsNull(inner) => Ok(Node::IsNull(Box::new(lower(inner, resolve)?))),
+        Term::Neg(inner) => Ok(Node::Neg(Box::new(lower(inner, resolve)?))),
+        Term::Add(t1, t2)
+        | Term::Sub(t1, t2)
+        | Term::Mul(t1, t2)
+        | Term::Div(t1, t2)
+        | Term::Mod(t1, t2) => {
+            let op = Arith::of(t).map(|(op, _, _)| op).expect("arithmetic term");
+            let n1 = lower(t1, resolve)?;
+            let n2 = lower(t2, resolve)?;
+            Ok(Node::Arith(op, Box::new(n1), Box::new(n2)))
+        }
+    }
+}
+
+fn lower_binary(
+    make: fn(Box<Node>, Box<Node>) -> Node,
+    t1: &Term,
+    t2: &Term,
+    resolve: &mut dyn FnMut(&str) -> Result<i64>,
+) -> Result<Node> {
+    let n1 = lower(t1, resolve)?;
+    let n2 = lower(t2, resolve)?;
+    Ok(make(Box::new(n1), Box::new(n2)))
+}
+
+/// The interpreter backend's Evaluator
+pub struct Interpreter {
+    program: Arc<Program>,
+    slots: RefCell<Vec<Value>>,
+}
+
+impl Interpreter {
+    /// Create an instance of ```program``` with all variables null
+    pub fn new(program: Arc<Program>) -> Interpreter {
+        Interpreter {
+            program,
+            slots: RefCell::new(Vec::new()),
+        }
+    }
+
+    fn set(&self, slot: i64, value: Value) -> Result<()> {
+        let slot = slot as usize;
+        let mut slots = self.slots.borrow_mut();
+        if slots.len() <= slot {
+            slots.resize(slot + 1, Value::Null);
+        }
+        slots[slot] = value;
+        Ok(())
+    }
+
+    fn eval(&self, node: &Node) -> Result<Value> {
+        match node {
+            Node::Const(value) => Ok(value.clone()),
+            Node::Slot(slot) => Ok(self
+                .slots
+                .borrow()
+                .get(*slot)
+                .cloned()
+                .unwrap_or(Value::Null)),
+            Node::Truthy(inner) => Ok(truth(truthy(&self.eval(inner)?)?)),
+            Node::Not(inner) => Ok(truth(as_truth(&self.eval(inner)?)?.map(|b| !b))),
+            // Like the wasm backend, and/or short-circuit: the right
+            // operand is skipped if the left one decides the result
+            Node::And(left, right) => match as_truth(&self.eval(left)?)? {
+                Some(false) => Ok(Value::Bool(false)),
+                left => Ok(truth(match (left, as_truth(&self.eval(right)?)?) {
+                    (_, Some(false)) => Some(false),
+                    (Some(true), right) => right,
+                    _ => None,
+                })),
+            },
+            Node::Or(left, right) => match as_truth(&self.eval(left)?)? {
+                Some(true) => Ok(Value::Bool(true)),
+                left => Ok(truth(match (left, as_truth(&self.eval(right)?)?) {
+                    (_, Some(true)) => Some(true),
+                    (Some(false), right) => right,
+                    _ => None,
+                })),
+            },
+            Node::Eq(left, right) => {
+                let (left, right) = (self.eval(left)?, self.eval(right)?);
+                if is_null(&left) || is_null(&right) {
+                    return Ok(Value::Null);
+                }
+                Ok(Value::Bool(equals(&left, &right)))
+            }
+            Node::Lt(left, right) => self.compare(left, right, |o| o == Ordering::Less),
+            Node::Le(left, right) => self.compare(left, right, |o| o != Ordering::Greater),
+            Node::Gt(left, right) => self.compare(left, right, |o| o == Ordering::Greater),
+            Node::Ge(left, right) => self.compare(left, right, |o| o != Ordering::Less),
+            Node::Matches(left, right) => {
+                let (value, regex) = (self.eval(left)?, self.eval(right)?);
+                if is_null(&value) {
+                    return Ok(Value::Null);
+                }
+                Ok(Value::Bool(matches(&value, &regex)?))
+            }
+            Node::In(subject, set) => match self.eval(subject)? {
+                Value::Null => Ok(Value::Null),
+                value => Ok(Value::Bool(set.contains(&value))),
+            },
+            Node::IsNull(inner) => Ok(Value::Bool(is_null(&self.eval(inner)?))),
+            Node::Arith(op, left, right) => arithmetic(*op, &self.eval(left)?, &self.eval(right)?),
+            Node::Neg(inner) => match self.eval(inner)? {
+                Value::Null => Ok(Value::Null),
+                Value::I64(intval) => Ok(Value::I64(intval.wrapping_neg())),
+                Value::F64(fval) => Ok(Value::F64(-fval)),
+                other => Err(Error::TypeMismatch(format!("cannot negate {:?}", other))),
+            },
+        }
+    }
+
+    fn compare(&self, left: &Node, right: &Node, test: fn(Ordering) -> bool) -> Result<Value> {
+        let left = self.eval(left)?;
+        let right = self.eval(right)?;
+        if is_null(&left) || is_null(&right) {
+            return Ok(Value::Null);
+        }
+        Ok(Value::Bool(order(&left, &right).map(test).unwrap_or(false)))
+    }
+}
+
+fn as_bool(value: &Value) -> Result<bool> {
+    match value {
+        Value::Bool(b) => Ok(*b),
+        other => Err(Error::TypeMismatch(format!(
+            "expected a boolean, got {:?}",
+            other
+        ))),
+    }
+}
+
+/// A boolean as a truth value of three-valued logic, where null is
+/// unknown (None)
+fn as_truth(value: &Value) -> Result<Option<bool>> {
+    match value {
+        Value::Null => Ok(None),
+        other => as_bool(other).map(Some),
+    }
+}
+
+/// The value of a truth value of three-valued logic
+fn truth(t: Option<bool>) -> Value {
+    t.map_or(Value::Null, Value::Bool)
+}
+
+/// Whether ```value``` is null
+fn is_null(value: &Value) -> bool {
+    match value {
+        Value::Null => true,
+        _ => false,
+    }
+}
+
+/// Truthiness of a bare variable: false, zero and empty strings and
+/// bytes are false, all other booleans, numbers, strings and bytes
+/// are true. Null is unknown.
+fn truthy(value: &Value) -> Result<Option<bool>> {
+    match value {
+        Value::Null => Ok(None),
+        Value::Bool(b) => Ok(Some(*b)),
+        Value::I64(intval) => Ok(Some(*intval != 0)),
+        Value::F64(fval) => Ok(Some(*fval != 0.0)),
+        Value::String(s) => Ok(Some(!s.is_empty())),
+        Value::Bytes(b) => Ok(Some(!b.is_empty())),
+        Value::Regex(_) => as_truth(value),
+    }
+}
+
+/// Apply ```op``` to two numbers, promoting an i64 operand to f64 if
+/// the other operand is a f64. The result is null if either operand
+/// is.
+fn arithmetic(op: Arith, left: &Value, right: &Value) -> Result<Value> {
+    match (left, right) {
+        (Value::Null, _) | (_, Value::Null) => Ok(Value::Null),
+        (Value::I64(l), Value::I64(r)) => Ok(Value::I64(op.apply_i64(*l, *r)?)),
+        (Value::F64(l), Value::F64(r)) => Ok(Value::F64(op.apply_f64(*l, *r))),
+        (Value::I64(l), Value::F64(r)) => Ok(Value::F64(op.apply_f64(*l as f64, *r))),
+        (Value::F64(l), Value::I64(r)) => Ok(Value::F64(op.apply_f64(*l, *r as f64))),
+        _ => Err(Error::TypeMismatch(format!(
+            "cannot apply {} to {:?} and {:?}",
+            op.symbol(),
+            left,
+            right
+        ))),
+    }
+}
+
+/// The bytes of a string or bytes value
+fn text(value: &Value) -> Option<&[u8]> {
+    match value {
+        Value::String(s) => Some(s.as_bytes()),
+        Value::Bytes(b) => Some(b),
+        _ => None,
+    }
+}
+
+/// Equality: numbers compare by value regardless of their type, and
+/// values of unrelated types are never equal.
+fn equals(left: &Value, right: &Value) -> bool {
+    match (left, right) {
+        (Value::Bool(l), Value::Bool(r)) => l == r,
+        _ => order(left, right) == Some(Ordering::Equal),
+    }
+}
+
+/// Whether ```value``` matches ```regex```. Only strings and bytes that
+/// are valid UTF-8 can match, other values never do.
+fn matches(value: &Value, regex: &Value) -> Result<bool> {
+    match regex {
+        Value::Regex(re) => Ok(text(value)
+            .and_then(|t| std::str::from_utf8(t).ok())
+            .map(|s| re.is_match(s))
+            .unwrap_or(false)),
+        other => Err(Error::TypeMismatch(format!(
+            "expected a regex, got {:?}",
+            other
+        ))),
+    }
+}
+
+/// Ordering of numbers (i64 operands are promoted to f64 when
+/// compared against a f64) and of strings and bytes
+/// (lexicographically by bytes). Other combinations are unordered.
+fn order(left: &Value, right: &Value) -> Option<Ordering> {
+    match (left, right) {
+        (Value::I64(l), Value::I64(r)) => Some(l.cmp(r)),
+        (Value::F64(l), Value::F64(r)) => l.partial_cmp(r),
+        (Value::I64(l), Value::F64(r)) => (*l as f64).partial_cmp(r),
+        (Value::F64(l), Value::I64(r)) => l.partial_cmp(&(*r as f64)),
+        _ => Some(text(left)?.cmp(text(right)?)),
+    }
+}
+
+impl Evaluator for Interpreter {
+    fn set_i64(&self, slot: i64, intval: i64) -> Result<()> {
+        self.set(slot, Value::I64(intval))
+    }
+
+    fn set_f64(&self, slot: i64, fval: f64) -> Result<()> {
+        self.set(slot, Value::F64(fval))
+    }
+
+    fn set_string(&self, slot: i64, string: &str) -> Result<()> {
+        self.set(slot, Value::String(Arc::from(string)))
+    }
+
+    fn set_bool(&self, slot: i64, bval: bool) -> Result<()> {
+        self.set(slot, Value::Bool(bval))
+    }
+
+    fn set_bytes(&self, slot: i64, bytes: &[u8]) -> Result<()> {
+        self.set(slot, Value::Bytes(Arc::from(bytes)))
+    }
+
+    fn set_null(&self, slot: i64) -> Result<()> {
+        self.set(slot, Value::Null)
+    }
+
+    fn run(&self) -> Result<Option<bool>> {
+        as_truth(&self.eval(&self.program.root)?)
+    }
+}
@@ -7,33 +7,42 @@
 //!
 //! Instantiating wormula has a few seconds overhead, due to AOT
 //! compilation of wasmer. This needs to be done once per thread,
-//! not formula.
+//! not formula. For formulas that are only evaluated a few times,
+//! a context using the interpreter backend avoids this overhead, see
+//! ```Context::with_backend```.
 //!
 //! Full usage example that does 1000000 iterations and then breaks:
-//! ```
-//!
-//! let mut context = Context::new();
-//! context.define_var("iterations");
+//! ```no_run
+//! # use wormula::evaluator::{Context, Type};
+//! # use wormula::parse::parse_formula;
+//! let mut context = Context::new()?;
+//! context.define_var_typed("iterations", Type::F64);
 //! let formula = "iterations == 0 or iterations == 1000000";
-//! let f1 = parse(formula).unwrap().1;
-//! let cf1 = context.compile(&f1);
-//! let if1 = cf1.instantiate();
+//! let f1 = parse_formula(formula)?;
+//! let cf1 = context.compile(&f1)?;
+//! let if1 = cf1.instantiate()?;
 //! let mut i = 0.0;
-//! let mut v = if1.get_variable("iterations").unwrap();
+//! let mut v = if1.get_typed_variable::<f64>("iterations")?;
 //! loop {
 //!     i += 1.0;
-//!     v.set_f64(i);
-//!     if if1.run() {
+//!     v.set(i)?;
+//!     if if1.run()? {
 //!         break;
 //!     }
 //! }
+//! # Ok::<(), wormula::Error>(())
 //! ```
 #[macro_use]
 extern crate wasmer_runtime;
 extern crate nom;
+extern crate regex;
 
-#[allow(dead_code)]
+pub mod error;
 pub mod evaluator;
-#[allow(dead_code)]
+pub mod interpreter;
 pub mod parse;
+pub mod record;
+pub mod simplify;
 pub mod term;
+
+pub use error::{Error, Result, UnknownIdentifier};
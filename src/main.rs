@@ -3,39 +3,45 @@ extern crate wormula;
 use std::time::Instant;
 
 use wormula::evaluator::*;
-use wormula::parse::parse;
+use wormula::parse::parse_formula;
 
 fn main() {
     let now = Instant::now();
     eprintln!("Compiling the wormula runtime library");
-    let mut context = Context::new();
+    let mut context = Context::new().expect("wormula runtime");
     eprintln!("Took {} ms", now.elapsed().as_millis());
 
-    context.define_var("iterations");
+    context.define_var_typed("iterations", Type::F64);
 
     let now = Instant::now();
     let formula = "iterations == 0 or iterations == 1000000";
     eprintln!("Parsing the formula {}", formula);
-    if let Ok((_, f1)) = parse(formula) {
+    let parsed = parse_formula(formula);
+    if let Err(e) = &parsed {
+        eprintln!("{}", e.render());
+    }
+    if let Ok(f1) = parsed {
         eprintln!("{} ms. AST: {:?}", now.elapsed().as_millis(), f1);
         eprintln!("Compiling formula+RTL");
         let now = Instant::now();
-        let cf1 = context.compile(&f1);
+        let cf1 = context.compile(&f1).expect("compile formula");
         eprintln!("{} ms. Instantiating formula", now.elapsed().as_millis());
         let now = Instant::now();
-        let if1 = cf1.instantiate();
+        let if1 = cf1.instantiate().expect("instantiate formula");
         eprintln!(
             "{} ms. Running a loop until the formula returns true...",
             now.elapsed().as_millis()
         );
         // Note that we can subsequently refer to the variable directly,
         // no need to use the string key.
-        let mut v = if1.get_variable("iterations").unwrap();
+        let mut v = if1
+            .get_typed_variable::<f64>("iterations")
+            .expect("get iterations");
         let mut i = 0.0;
         loop {
             i += 1.0;
-            v.set_f64(i);
-            if if1.run() {
+            v.set(i).expect("set iterations");
+            if if1.run().expect("run formula") {
                 break;
             }
         }
@@ -1,19 +1,26 @@
 use nom::{
     branch::alt,
-    bytes::complete::{tag, take_until, take_while, take_while1},
-    combinator::map,
+    bytes::complete::{tag, tag_no_case, take_while, take_while1},
+    combinator::{cut, map, opt},
+    error::{context, ErrorKind, ParseError as NomParseError, VerboseError, VerboseErrorKind},
     number::complete::double,
     //          multi::{many1, separated_nonempty_list},
     //          ParseTo
     IResult,
 };
+use std::borrow::Cow;
+use std::fmt;
 
 use crate::term::*;
 
-enum Operator0 {
-    And,
-    Or,
-}
+/// Words that have a meaning in the grammar and thus cannot be
+/// used as variable names.
+const KEYWORDS: &[&str] = &["and", "or", "not", "in", "is", "null", "true", "false"];
+
+type PResult<'a, T> = IResult<&'a str, T, VerboseError<&'a str>>;
+
+/// Constructor of a binary operator's term
+type BinaryOp<'a> = fn(Box<Term<'a>>, Box<Term<'a>>) -> Term<'a>;
 
 enum Operator1 {
     Eq,
@@ -22,93 +29,666 @@ enum Operator1 {
     Ge,
     Lt,
     Le,
+    Matches,
+    NotMatches,
+}
+
+/// A formula that could not be parsed, along with the position
+/// of the problem within the formula.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    /// Byte offset of the offending span within the formula
+    pub offset: usize,
+    /// Length of the offending span in bytes
+    pub len: usize,
+    /// Line of the offending span, starting at 1
+    pub line: usize,
+    /// Column (in characters) of the offending span, starting at 1
+    pub column: usize,
+    /// What the parser expected to find at ```offset```
+    pub expected: &'static str,
+    /// The complete source line containing the offending span
+    pub line_text: String,
+}
+
+impl ParseError {
+    fn new(source: &str, offset: usize, len: usize, expected: &'static str) -> ParseError {
+        let line_start = source[..offset].rfind('\n').map(|i| i + 1).unwrap_or(0);
+        let line_end = source[offset..]
+            .find('\n')
+            .map(|i| offset + i)
+            .unwrap_or_else(|| source.len());
+        let line = source[..offset].matches('\n').count() + 1;
+        let column = source[line_start..offset].chars().count() + 1;
+        let len = len.min(line_end - offset);
+        ParseError {
+            offset,
+            len,
+            line,
+            column,
+            expected,
+            line_text: source[line_start..line_end].to_string(),
+        }
+    }
+
+    /// Render the offending source line with the problematic span
+    /// underlined by carets, e.g. for display in a user interface.
+    pub fn render(&self) -> String {
+        let start = self
+            .line_text
+            .char_indices()
+            .nth(self.column - 1)
+            .map(|(i, _)| i)
+            .unwrap_or_else(|| self.line_text.len());
+        // Keep tabs so that the carets line up with the source line
+        let prefix: String = self.line_text[..start]
+            .chars()
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        let carets = self.line_text[start..]
+            .get(..self.len)
+            .map(|s| s.chars().count())
+            .unwrap_or(0)
+            .max(1);
+        let gutter = self.line.to_string();
+        format!(
+            "{}\n{} | {}\n{} | {}{}",
+            self,
+            gutter,
+            self.line_text,
+            " ".repeat(gutter.len()),
+            prefix,
+            "^".repeat(carets)
+        )
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "line {}, column {}: expected {}",
+            self.line, self.column, self.expected
+        )
+    }
 }
 
-fn w_string(input: &str) -> IResult<&str, Term> {
-    let (input, _) = tag("\"")(input)?;
-    let (input, s) = take_until("\"")(input)?;
-    let (input, _) = tag("\"")(input)?;
-    Ok((input, Term::String(s)))
+/// A string literal in double or single quotes. Backslash escapes
+/// ```\\```, ```\"```, ```\'```, ```\n```, ```\t```, ```\r```, ```\0```
+/// and ```\u{...}``` (one to six hex digits) are decoded; a literal
+/// without escapes borrows from the input.
+fn w_string(input: &str) -> PResult<Term> {
+    let quote = match input.chars().next() {
+        Some(c @ '"') | Some(c @ '\'') => c,
+        _ => {
+            return Err(nom::Err::Error(VerboseError::from_error_kind(
+                input,
+                ErrorKind::Tag,
+            )))
+        }
+    };
+    let body = &input[1..];
+    let failure = |expected: &'static str, rest| {
+        nom::Err::Failure(VerboseError {
+            errors: vec![(rest, VerboseErrorKind::Context(expected))],
+        })
+    };
+    let mut decoded: Option<String> = None;
+    let mut pos = 0;
+    while let Some(c) = body[pos..].chars().next() {
+        if c == quote {
+            let s = match decoded {
+                Some(owned) => Cow::Owned(owned),
+                None => Cow::Borrowed(&body[..pos]),
+            };
+            return Ok((&body[pos + 1..], Term::String(s)));
+        }
+        if c != '\\' {
+            if let Some(owned) = decoded.as_mut() {
+                owned.push(c);
+            }
+            pos += c.len_utf8();
+            continue;
+        }
+        let (escaped, len) = match body[pos + 1..].chars().next() {
+            Some('\\') => ('\\', 2),
+            Some('"') => ('"', 2),
+            Some('\'') => ('\'', 2),
+            Some('n') => ('\n', 2),
+            Some('t') => ('\t', 2),
+            Some('r') => ('\r', 2),
+            Some('0') => ('\0', 2),
+            Some('u') => {
+                let braced = &body[pos + 2..];
+                let hex = match (braced.starts_with('{'), braced.find('}')) {
+                    (true, Some(end)) if (2..=7).contains(&end) => &braced[1..end],
+                    _ => "",
+                };
+                if hex.is_empty() || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+                    return Err(failure("unicode escape", &body[pos..]));
+                }
+                let c = u32::from_str_radix(hex, 16)
+                    .ok()
+                    .and_then(std::char::from_u32)
+                    .ok_or_else(|| failure("unicode escape", &body[pos..]))?;
+                (c, hex.len() + 4)
+            }
+            Some(_) => return Err(failure("escape sequence", &body[pos..])),
+            None => break,
+        };
+        decoded
+            .get_or_insert_with(|| body[..pos].to_string())
+            .push(escaped);
+        pos += len;
+    }
+    Err(failure("closing quote", body))
 }
 
-fn w_float(input: &str) -> IResult<&str, Term> {
+fn w_float(input: &str) -> PResult<Term> {
     let (input, n) = double(input)?;
     Ok((input, Term::Float(n)))
 }
 
-fn w_operator0(input: &str) -> IResult<&str, Operator0> {
-    alt((
-        map(tag("and"), |_| Operator0::And),
-        map(tag("or"), |_| Operator0::Or),
-    ))(input)
+/// Digits in base ```radix```, possibly separated by underscores
+fn w_digits<'a>(radix: u32) -> impl Fn(&'a str) -> PResult<'a, &'a str> {
+    move |input: &'a str| {
+        let (rest, digits) = take_while1(|c: char| c == '_' || c.is_digit(radix))(input)?;
+        if digits.starts_with('_') {
+            return Err(nom::Err::Error(VerboseError::from_error_kind(
+                input,
+                ErrorKind::Digit,
+            )));
+        }
+        Ok((rest, digits))
+    }
+}
+
+/// An integer literal: decimal, hexadecimal with prefix ```0x``` or
+/// binary with prefix ```0b```, optionally negative. Digits may be
+/// separated by underscores, e.g. ```1_000_000```. Decimal numbers
+/// with a fraction or exponent are left to ```w_float```.
+fn w_integer(input: &str) -> PResult<Term> {
+    let (rest, minus) = opt(tag("-"))(input)?;
+    let (rest, radix) = opt(alt((
+        map(tag_no_case("0x"), |_| 16),
+        map(tag_no_case("0b"), |_| 2),
+    )))(rest)?;
+    let (rest, digits) = match radix {
+        Some(radix) => cut(context("digits", w_digits(radix)))(rest)?,
+        None => w_digits(10)(rest)?,
+    };
+    if radix.is_none() && rest.starts_with(|c: char| c == '.' || c == 'e' || c == 'E') {
+        return Err(nom::Err::Error(VerboseError::from_error_kind(
+            input,
+            ErrorKind::Digit,
+        )));
+    }
+    let mut literal = String::with_capacity(digits.len() + 1);
+    if minus.is_some() {
+        literal.push('-');
+    }
+    literal.extend(digits.chars().filter(|c| *c != '_'));
+    match i64::from_str_radix(&literal, radix.unwrap_or(10)) {
+        Ok(n) => Ok((rest, Term::Int(n))),
+        Err(_) => Err(nom::Err::Failure(VerboseError {
+            errors: vec![(input, VerboseErrorKind::Context("integer within range"))],
+        })),
+    }
 }
 
-fn w_operator1(input: &str) -> IResult<&str, Operator1> {
+/// Whitespace and comments, which may appear between any two tokens:
+/// ```# line comments``` and ```/* block comments */```. Block
+/// comments do not nest. Returns the skipped input.
+fn w_space(input: &str) -> PResult<&str> {
+    let mut rest = input;
+    loop {
+        rest = rest.trim_start();
+        if rest.starts_with('#') {
+            rest = rest.find('\n').map_or("", |end| &rest[end..]);
+        } else if rest.starts_with("/*") {
+            match rest[2..].find("*/") {
+                Some(end) => rest = &rest[end + 4..],
+                None => {
+                    return Err(nom::Err::Failure(VerboseError {
+                        errors: vec![(rest, VerboseErrorKind::Context("end of comment"))],
+                    }))
+                }
+            }
+        } else {
+            return Ok((rest, &input[..input.len() - rest.len()]));
+        }
+    }
+}
+
+/// Match a keyword such as ```and``` that must not be directly
+/// followed by further identifier characters, including the ```.```
+/// and ```[``` of record paths.
+fn w_keyword<'a>(kw: &'static str) -> impl Fn(&'a str) -> PResult<'a, &'a str> {
+    move |input: &'a str| {
+        let (rest, k) = tag(kw)(input)?;
+        match rest.chars().next() {
+            Some(c) if c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '[' => Err(
+                nom::Err::Error(VerboseError::from_error_kind(input, ErrorKind::Tag)),
+            ),
+            _ => Ok((rest, k)),
+        }
+    }
+}
+
+fn w_operator1(input: &str) -> PResult<Operator1> {
     alt((
+        map(tag("=~"), |_| Operator1::Matches),
+        map(tag("!~"), |_| Operator1::NotMatches),
         map(tag("=="), |_| Operator1::Eq),
         map(tag("!="), |_| Operator1::NEq),
+        map(tag("<="), |_| Operator1::Le),
+        map(tag(">="), |_| Operator1::Ge),
+        map(tag("<"), |_| Operator1::Lt),
+        map(tag(">"), |_| Operator1::Gt),
     ))(input)
 }
 
-fn w_op0(input: &str) -> IResult<&str, Term> {
-    let (input, left) = w_op1(input)?;
-    let (input, _) = take_while(|c: char| c.is_whitespace())(input)?;
-    let (input, operator) = w_operator0(input)?;
-    let (input, _) = take_while(|c: char| c.is_whitespace())(input)?;
-    let (input, right) = w_term(input)?;
-    let bl = Box::new(left);
-    let br = Box::new(right);
-    match operator {
-        Operator0::And => Ok((input, Term::And(bl, br))),
-        Operator0::Or => Ok((input, Term::Or(bl, br))),
-    }
-}
-
-fn w_op1(input: &str) -> IResult<&str, Term> {
-    let (input, left) = w_value(input)?;
-    let (input, _) = take_while(|c: char| c.is_whitespace())(input)?;
-    let (input, operator) = w_operator1(input)?;
-    let (input, _) = take_while(|c: char| c.is_whitespace())(input)?;
-    let (input, right) = w_term(input)?;
-    let bl = Box::new(left);
-    let br = Box::new(right);
-    Ok((
-        input,
-        match operator {
-            Operator1::Eq => Term::Eq(bl, br),
-            Operator1::NEq => Term::Not(Box::new(Term::Eq(bl, br))),
-            Operator1::Lt => Term::Lt(bl, br),
-            Operator1::Le => Term::Le(bl, br),
-            Operator1::Gt => Term::Gt(bl, br),
-            Operator1::Ge => Term::Ge(bl, br),
-        },
-    ))
+/// Parse a left associative chain of operands separated by the
+/// keyword ```kw```, combining them with ```make```.
+fn w_chain0<'a>(
+    input: &'a str,
+    kw: &'static str,
+    make: fn(Box<Term<'a>>, Box<Term<'a>>) -> Term<'a>,
+    operand: fn(&'a str) -> PResult<'a, Term<'a>>,
+) -> PResult<'a, Term<'a>> {
+    let (mut input, mut left) = operand(input)?;
+    loop {
+        let attempt = w_space(input)
+            .and_then(|(i, _)| w_keyword(kw)(i))
+            .and_then(|(i, _)| w_space(i));
+        match attempt {
+            Ok((rest, _)) => {
+                let (rest, right) = cut(context("value", operand))(rest)?;
+                left = make(Box::new(left), Box::new(right));
+                input = rest;
+            }
+            Err(nom::Err::Error(_)) => return Ok((input, left)),
+            Err(e) => return Err(e),
+        }
+    }
 }
 
-fn w_identifier(input: &str) -> IResult<&str, Term> {
-    let (input, ident) = take_while1(|c: char| c.is_ascii_alphanumeric())(input)?;
-    Ok((input, Term::Variable(ident)))
+fn w_or(input: &str) -> PResult<Term> {
+    w_chain0(input, "or", Term::Or, w_and)
 }
 
-fn w_regex(input: &str) -> IResult<&str, Term> {
-    let (input, _) = tag("/")(input)?;
-    let (input, re_str) = take_while1(|c: char| c != '/')(input)?;
+fn w_and(input: &str) -> PResult<Term> {
+    w_chain0(input, "and", Term::And, w_comparison)
+}
+
+/// ```in``` or ```not in```; true for the latter
+fn w_membership(input: &str) -> PResult<bool> {
+    alt((
+        map(w_keyword("in"), |_| false),
+        map(
+            |input| {
+                let (input, _) = w_keyword("not")(input)?;
+                let (input, _) = w_space(input)?;
+                w_keyword("in")(input)
+            },
+            |_| true,
+        ),
+    ))(input)
+}
+
+/// ```is null``` or ```is not null```; true for the latter
+fn w_null_test(input: &str) -> PResult<bool> {
+    let (input, _) = w_keyword("is")(input)?;
+    let (input, _) = w_space(input)?;
+    let (input, negated) = opt(|input| {
+        let (input, _) = w_keyword("not")(input)?;
+        w_space(input)
+    })(input)?;
+    let (input, _) = cut(context("null", w_keyword("null")))(input)?;
+    Ok((input, negated.is_some()))
+}
+
+/// A parenthesized, comma separated list of literals, e.g.
+/// ```("DE", "AT", 3)```. A trailing comma is allowed.
+fn w_list(input: &str) -> PResult<Vec<Term>> {
+    let (mut input, _) = cut(context("list", tag("(")))(input)?;
+    let mut elements = Vec::new();
+    loop {
+        let (rest, _) = w_space(input)?;
+        if let Ok((rest, _)) = tag::<_, _, VerboseError<&str>>(")")(rest) {
+            return Ok((rest, elements));
+        }
+        let (rest, element) = cut(context(
+            "literal",
+            alt((w_string, w_integer, w_float, w_bool)),
+        ))(rest)?;
+        elements.push(element);
+        let (rest, _) = w_space(rest)?;
+        let (rest, comma) = opt(tag(","))(rest)?;
+        if comma.is_none() {
+            let (rest, _) = cut(context("closing parenthesis", tag(")")))(rest)?;
+            return Ok((rest, elements));
+        }
+        input = rest;
+    }
+}
+
+fn w_comparison(input: &str) -> PResult<Term> {
+    let (mut input, mut left) = w_additive(input)?;
+    loop {
+        let membership = w_space(input)
+            .and_then(|(i, _)| w_membership(i))
+            .and_then(|(i, negated)| w_space(i).map(|(i, _)| (i, negated)));
+        match membership {
+            Ok((rest, negated)) => {
+                let (rest, list) = w_list(rest)?;
+                left = Term::In(Box::new(left), list);
+                if negated {
+                    left = Term::Not(Box::new(left));
+                }
+                input = rest;
+                continue;
+            }
+            Err(nom::Err::Error(_)) => {}
+            Err(e) => return Err(e),
+        }
+        match w_space(input).and_then(|(i, _)| w_null_test(i)) {
+            Ok((rest, negated)) => {
+                left = Term::IsNull(Box::new(left));
+                if negated {
+                    left = Term::Not(Box::new(left));
+                }
+                input = rest;
+                continue;
+            }
+            Err(nom::Err::Error(_)) => {}
+            Err(e) => return Err(e),
+        }
+        let attempt = w_space(input)
+            .and_then(|(i, _)| w_operator1(i))
+            .and_then(|(i, o)| w_space(i).map(|(i, _)| (i, o)));
+        match attempt {
+            Ok((rest, operator)) => {
+                let (rest, right) = cut(context("value", w_additive))(rest)?;
+                let bl = Box::new(left);
+                let br = Box::new(right);
+                left = match operator {
+                    Operator1::Eq => Term::Eq(bl, br),
+                    Operator1::NEq => Term::Not(Box::new(Term::Eq(bl, br))),
+                    Operator1::Lt => Term::Lt(bl, br),
+                    Operator1::Le => Term::Le(bl, br),
+                    Operator1::Gt => Term::Gt(bl, br),
+                    Operator1::Ge => Term::Ge(bl, br),
+                    Operator1::Matches => Term::Matches(bl, br),
+                    Operator1::NotMatches => Term::Not(Box::new(Term::Matches(bl, br))),
+                };
+                input = rest;
+            }
+            Err(nom::Err::Error(_)) => return Ok((input, left)),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+fn w_operator2<'a>(input: &'a str) -> PResult<'a, BinaryOp<'a>> {
+    alt((
+        map(tag("+"), |_| Term::Add as BinaryOp<'a>),
+        map(tag("-"), |_| Term::Sub as BinaryOp<'a>),
+    ))(input)
+}
+
+fn w_operator3<'a>(input: &'a str) -> PResult<'a, BinaryOp<'a>> {
+    alt((
+        map(tag("*"), |_| Term::Mul as BinaryOp<'a>),
+        map(tag("/"), |_| Term::Div as BinaryOp<'a>),
+        map(tag("%"), |_| Term::Mod as BinaryOp<'a>),
+    ))(input)
+}
+
+/// Parse a left associative chain of operands separated by the
+/// operators recognized by ```operator```.
+fn w_operator_chain<'a>(
+    input: &'a str,
+    operator: fn(&'a str) -> PResult<'a, BinaryOp<'a>>,
+    operand: fn(&'a str) -> PResult<'a, Term<'a>>,
+) -> PResult<'a, Term<'a>> {
+    let (mut input, mut left) = operand(input)?;
+    loop {
+        let attempt = w_space(input)
+            .and_then(|(i, _)| operator(i))
+            .and_then(|(i, o)| w_space(i).map(|(i, _)| (i, o)));
+        match attempt {
+            Ok((rest, make)) => {
+                let (rest, right) = cut(context("value", operand))(rest)?;
+                left = make(Box::new(left), Box::new(right));
+                input = rest;
+            }
+            Err(nom::Err::Error(_)) => return Ok((input, left)),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+fn w_additive(input: &str) -> PResult<Term> {
+    w_operator_chain(input, w_operator2, w_multiplicative)
+}
+
+/// A ```/``` following an operand is always the division operator;
+/// regex literals can only start where an operand is expected.
+fn w_multiplicative(input: &str) -> PResult<Term> {
+    w_operator_chain(input, w_operator3, w_unary)
+}
+
+fn w_neg(input: &str) -> PResult<Term> {
+    let (input, _) = tag("-")(input)?;
+    let (input, _) = w_space(input)?;
+    let (input, inner) = cut(context("value", w_unary))(input)?;
+    Ok((input, Term::Neg(Box::new(inner))))
+}
+
+fn w_bool(input: &str) -> PResult<Term> {
+    alt((
+        map(w_keyword("true"), |_| Term::Bool(true)),
+        map(w_keyword("false"), |_| Term::Bool(false)),
+    ))(input)
+}
+
+fn w_not(input: &str) -> PResult<Term> {
+    let (input, _) = alt((w_keyword("not"), tag("!")))(input)?;
+    let (input, _) = w_space(input)?;
+    let (input, inner) = w_unary(input)?;
+    Ok((input, Term::Not(Box::new(inner))))
+}
+
+/// Unary operators. A minus directly followed by a number is part of
+/// the number literal.
+fn w_unary(input: &str) -> PResult<Term> {
+    alt((w_not, w_primary, w_neg))(input)
+}
+
+fn w_parens(input: &str) -> PResult<Term> {
+    let (input, _) = tag("(")(input)?;
+    let (input, _) = w_space(input)?;
+    let (input, inner) = cut(context("value", w_or))(input)?;
+    let (input, _) = w_space(input)?;
+    let (input, _) = cut(context("closing parenthesis", tag(")")))(input)?;
+    Ok((input, inner))
+}
+
+fn w_primary(input: &str) -> PResult<Term> {
+    context("value", alt((w_parens, w_value)))(input)
+}
+
+/// An identifier of letters, digits and underscores, optionally
+/// followed by a path of ```.field``` and ```[index]``` accessors into
+/// a structured record, e.g. ```http.status``` or ```tags[0]```. The
+/// variable is named by the whole path.
+fn w_identifier(input: &str) -> PResult<Term> {
+    let is_name = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    let (mut rest, ident) = take_while1(is_name)(input)?;
+    if KEYWORDS.contains(&ident) {
+        return Err(nom::Err::Error(VerboseError::from_error_kind(
+            input,
+            ErrorKind::Tag,
+        )));
+    }
+    loop {
+        if rest.starts_with('.') {
+            let (after, _) = cut(context("field name", take_while1(is_name)))(&rest[1..])?;
+            rest = after;
+        } else if rest.starts_with('[') {
+            let index = take_while1(|c: char| c.is_ascii_digit());
+            let (after, _) = cut(context("index", index))(&rest[1..])?;
+            let (after, _) = cut(context("closing bracket", tag("]")))(after)?;
+            rest = after;
+        } else {
+            break;
+        }
+    }
+    Ok((
+        rest,
+        Term::Variable(Cow::Borrowed(&input[..input.len() - rest.len()])),
+    ))
+}
+
+/// A regex literal, ```/pattern/flags```. A slash inside the pattern
+/// is written ```\/```; other escapes are left to the regex engine.
+/// The flags are any of ```i```, ```m```, ```s``` and ```x```, see
+/// ```RegexFlags```.
+fn w_regex(input: &str) -> PResult<Term> {
     let (input, _) = tag("/")(input)?;
-    Ok((input, Term::Regex(re_str)))
+    let failure = |expected: &'static str, rest| {
+        nom::Err::Failure(VerboseError {
+            errors: vec![(rest, VerboseErrorKind::Context(expected))],
+        })
+    };
+    let mut decoded: Option<String> = None;
+    let mut pos = 0;
+    let pattern = loop {
+        match input[pos..].chars().next() {
+            Some('/') if pos == 0 => return Err(failure("regex", input)),
+            Some('/') => {
+                break match decoded {
+                    Some(owned) => Cow::Owned(owned),
+                    None => Cow::Borrowed(&input[..pos]),
+                }
+            }
+            Some('\\') if input[pos + 1..].starts_with('/') => {
+                decoded
+                    .get_or_insert_with(|| input[..pos].to_string())
+                    .push('/');
+                pos += 2;
+            }
+            Some(c) => {
+                // Keep an escaped character together with its backslash
+                let len = match c {
+                    '\\' => 1 + input[pos + 1..].chars().next().map_or(0, char::len_utf8),
+                    c => c.len_utf8(),
+                };
+                if let Some(owned) = decoded.as_mut() {
+                    owned.push_str(&input[pos..pos + len]);
+                }
+                pos += len;
+            }
+            None if pos == 0 => return Err(failure("regex", input)),
+            None => return Err(failure("closing slash", &input[pos..])),
+        }
+    };
+    let (rest, letters) = take_while(|c: char| c.is_ascii_alphanumeric())(&input[pos + 1..])?;
+    let mut flags = RegexFlags::default();
+    for (i, letter) in letters.char_indices() {
+        if !flags.set(letter) {
+            return Err(failure("regex flag", &letters[i..]));
+        }
+    }
+    Ok((rest, Term::Regex(pattern, flags)))
 }
 
-fn w_value(input: &str) -> IResult<&str, Term> {
-    alt((w_regex, w_string, w_float, w_identifier))(input)
+fn w_value(input: &str) -> PResult<Term> {
+    alt((w_regex, w_string, w_integer, w_float, w_bool, w_identifier))(input)
 }
 
-fn w_term(input: &str) -> IResult<&str, Term> {
-    alt((w_op0, w_op1, w_value))(input)
+/// Parse a complete term. Operators bind, from tightest to loosest:
+/// ```not``` (or ```!```) and unary ```-```, ```*```, ```/``` and ```%```, ```+```
+/// and binary ```-```, comparisons (```==```, ```!=```, ```<```,
+/// ```<=```, ```>```, ```>=```, ```=~```, ```!~```, ```in```,
+/// ```not in```, ```is null``` and ```is not null```), ```and```,
+/// ```or```. All binary operators are left associative; parentheses
+/// may be used for grouping.
+fn w_term(input: &str) -> PResult<Term> {
+    w_or(input)
 }
 
 /// Parse a formula string, return an AST that can subsequently be
 /// passed to a call to Context::new().compile()
 /// The same AST can be reused for compilation with
 /// multiple contexts.
-pub fn parse(input: &str) -> IResult<&str, Term> {
-    w_term(input)
+///
+/// Whitespace and comments may surround the formula and separate its
+/// tokens, see ```w_space```. Parsing stops at the first input that
+/// cannot be part of the formula, which is returned alongside the
+/// AST. Use parse_formula to require that the complete input is a
+/// valid formula.
+pub fn parse(input: &str) -> PResult<Term> {
+    let (input, _) = w_space(input)?;
+    let (rest, term) = w_term(input)?;
+    let (rest, _) = w_space(rest)?;
+    Ok((rest, term))
+}
+
+/// Parse a complete formula string. Unlike ```parse```, trailing
+/// input that is not part of the formula is an error. On failure,
+/// the returned error describes the position of the problem and
+/// what was expected there.
+pub fn parse_formula(source: &str) -> Result<Term, ParseError> {
+    let offset = |rest: &str| source.len() - rest.len();
+    match parse(source) {
+        Ok((rest, term)) => {
+            if rest.is_empty() {
+                Ok(term)
+            } else {
+                Err(ParseError::new(
+                    source,
+                    offset(rest),
+                    token_len(rest),
+                    "operator or end of input",
+                ))
+            }
+        }
+        Err(nom::Err::Error(e)) | Err(nom::Err::Failure(e)) => {
+            let innermost_context = e.errors.iter().find_map(|(rest, kind)| match kind {
+                VerboseErrorKind::Context(ctx) => Some((*rest, *ctx)),
+                _ => None,
+            });
+            let (rest, expected) = match innermost_context {
+                Some(found) => found,
+                None => (e.errors.first().map(|(r, _)| *r).unwrap_or(source), "value"),
+            };
+            Err(ParseError::new(
+                source,
+                offset(rest),
+                token_len(rest),
+                expected,
+            ))
+        }
+        Err(nom::Err::Incomplete(_)) => Err(ParseError::new(source, source.len(), 0, "value")),
+    }
+}
+
+/// Length of the token at the start of ```input```, used to size
+/// the span of a ParseError.
+fn token_len(input: &str) -> usize {
+    let first = match input.chars().next() {
+        Some(c) => c,
+        None => return 0,
+    };
+    if first.is_ascii_alphanumeric() || first == '_' {
+        input
+            .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+            .unwrap_or_else(|| input.len())
+    } else if first == '"' || first == '\'' || first == '/' {
+        input.find('\n').unwrap_or_else(|| input.len())
+    } else {
+        first.len_utf8()
+    }
 }
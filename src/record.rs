@@ -0,0 +1,97 @@
+//! Structured records, e.g. decoded JSON documents, whose nested
+//! fields are assigned to the variables named by their path with
+//! ```InstantiatedTerm::set_record```.
+use std::collections::HashMap;
+
+/// A value of a structured record
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    I64(i64),
+    F64(f64),
+    String(String),
+    Bytes(Vec<u8>),
+    List(Vec<Value>),
+    Record(HashMap<String, Value>),
+}
+
+impl Value {
+    /// A record of the given fields
+    pub fn record<'k, I: IntoIterator<Item = (&'k str, Value)>>(fields: I) -> Value {
+        Value::Record(
+            fields
+                .into_iter()
+                .map(|(name, value)| (name.to_string(), value))
+                .collect(),
+        )
+    }
+
+    /// The value found at ```path```, a field name followed by any
+    /// number of ```.field``` and ```[index]``` accessors, as in
+    /// ```http.status``` or ```tags[0].name```. None if a field does
+    /// not exist, an index is out of bounds or a value is accessed
+    /// like a record or list that is not one.
+    pub fn get(&self, path: &str) -> Option<&Value> {
+        let mut value = self;
+        let mut rest = path;
+        while !rest.is_empty() {
+            if rest.starts_with('[') {
+                let end = rest.find(']')?;
+                let index: usize = rest[1..end].parse().ok()?;
+                value = match value {
+                    Value::List(items) => items.get(index)?,
+                    _ => return None,
+                };
+                rest = &rest[end + 1..];
+            } else {
+                let start = if rest.starts_with('.') { 1 } else { 0 };
+                let end = rest[start..]
+                    .find(&['.', '['][..])
+                    .map_or(rest.len(), |end| start + end);
+                value = match value {
+                    Value::Record(fields) => fields.get(&rest[start..end])?,
+                    _ => return None,
+                };
+                rest = &rest[end..];
+            }
+        }
+        Some(value)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(bval: bool) -> Value {
+        Value::Bool(bval)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(intval: i64) -> Value {
+        Value::I64(intval)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(fval: f64) -> Value {
+        Value::F64(fval)
+    }
+}
+
+impl<'s> From<&'s str> for Value {
+    fn from(string: &'s str) -> Value {
+        Value::String(string.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(string: String) -> Value {
+        Value::String(string)
+    }
+}
+
+impl<V: Into<Value>> From<Vec<V>> for Value {
+    fn from(items: Vec<V>) -> Value {
+        Value::List(items.into_iter().map(Into::into).collect())
+    }
+}
@@ -0,0 +1,307 @@
+//! Simplification of terms between parsing and compilation. Formulas
+//! generated by tools often contain redundancy such as
+//! ```1 == 1 and x == 5``` or repeated clauses, which the simplifier
+//! removes so that it is not evaluated over and over again.
+use std::cmp::Ordering;
+
+use crate::term::*;
+
+/// Simplify ```t``` without changing its result:
+///
+/// * arithmetic on numeric literals is computed, except for integer
+///   division by zero which is left to fail at runtime
+/// * comparisons between literals of comparable types are folded into
+///   ```Term::Bool```
+/// * boolean constants are propagated through ```and```, ```or```
+///   and ```not```
+/// * ```not not t``` becomes ```t```
+/// * repeated operands of a chain of ```and``` or ```or``` are removed,
+///   as are repeated literals of an ```in``` list
+/// * ```c and not c``` becomes false and ```c or not c``` true, for
+///   any null test ```c```; other comparisons may be unknown, see
+///   below
+/// * null tests of literals become false
+///
+/// Under three-valued logic, comparisons involving null are unknown,
+/// and so are both ```c and not c``` and ```c or not c```. The
+/// simplifications above never change a result, including unknown.
+///
+/// Operands that cannot affect the result are dropped entirely, so
+/// runtime type errors they would have caused do not occur.
+///
+/// ```Context::compile``` simplifies every formula before compiling
+/// it; call this function to inspect what is actually compiled.
+pub fn simplify<'a>(t: &Term<'a>) -> Term<'a> {
+    simplify_in(t, true)
+}
+
+/// Simplify ```t```; ```boolean``` is set if ```t``` is in boolean
+/// position, i.e. not the operand of a comparison.
+fn simplify_in<'a>(t: &Term<'a>, boolean: bool) -> Term<'a> {
+    match t {
+        Term::Not(inner) => match simplify_in(inner, true) {
+            Term::Bool(b) => Term::Bool(!b),
+            // Only drop both negations if that does not turn a
+            // boolean into a value of another type
+            Term::Not(twice) if boolean || is_boolean(&twice) => *twice,
+            other => Term::Not(Box::new(other)),
+        },
+        Term::And(_, _) => simplify_chain(t, true, boolean),
+        Term::Or(_, _) => simplify_chain(t, false, boolean),
+        Term::Eq(t1, t2)
+        | Term::Lt(t1, t2)
+        | Term::Le(t1, t2)
+        | Term::Gt(t1, t2)
+        | Term::Ge(t1, t2)
+        | Term::Matches(t1, t2) => {
+            let left = simplify_in(t1, false);
+            let right = simplify_in(t2, false);
+            if let Some(b) = fold_comparison(t, &left, &right) {
+                return Term::Bool(b);
+            }
+            let (left, right) = (Box::new(left), Box::new(right));
+            match t {
+                Term::Eq(_, _) => Term::Eq(left, right),
+                Term::Lt(_, _) => Term::Lt(left, right),
+                Term::Le(_, _) => Term::Le(left, right),
+                Term::Gt(_, _) => Term::Gt(left, right),
+                Term::Ge(_, _) => Term::Ge(left, right),
+                _ => Term::Matches(left, right),
+            }
+        }
+        Term::In(subject, list) => {
+            let subject = simplify_in(subject, false);
+            if is_literal(&subject) && list.iter().all(|element| equatable(&subject, element)) {
+                let found = list.iter().any(|element| literals_equal(&subject, element));
+                return Term::Bool(found);
+            }
+            let mut unique: Vec<Term> = Vec::with_capacity(list.len());
+            for element in list {
+                if !unique.contains(element) {
+                    unique.push(element.clone());
+                }
+            }
+            if unique.is_empty() {
+                return Term::Bool(false);
+            }
+            Term::In(Box::new(subject), unique)
+        }
+        Term::IsNull(inner) => {
+            let inner = simplify_in(inner, false);
+            if is_literal(&inner) {
+                return Term::Bool(false);
+            }
+            Term::IsNull(Box::new(inner))
+        }
+        Term::Neg(inner) => match simplify_in(inner, false) {
+            Term::Int(intval) => Term::Int(intval.wrapping_neg()),
+            Term::Float(fval) => Term::Float(-fval),
+            other => Term::Neg(Box::new(other)),
+        },
+        Term::Add(t1, t2)
+        | Term::Sub(t1, t2)
+        | Term::Mul(t1, t2)
+        | Term::Div(t1, t2)
+        | Term::Mod(t1, t2) => {
+            let (op, _, _) = Arith::of(t).expect("arithmetic term");
+            let left = simplify_in(t1, false);
+            let right = simplify_in(t2, false);
+            fold_arith(op, &left, &right).unwrap_or_else(|| op.make(left, right))
+        }
+        leaf => leaf.clone(),
+    }
+}
+
+/// Simplify a chain of ```and``` (if ```and``` is set) or ```or```;
+/// ```boolean``` is set if the chain is in boolean position.
+fn simplify_chain<'a>(t: &Term<'a>, and: bool, boolean: bool) -> Term<'a> {
+    let mut operands = Vec::new();
+    for operand in chain_operands(t, and) {
+        match simplify_in(operand, true) {
+            // The identity element can be dropped, the absorbing
+            // element decides the result
+            Term::Bool(b) if b == and => {}
+            Term::Bool(b) => return Term::Bool(b),
+            simplified => {
+                for operand in chain_operands(&simplified, and) {
+                    if !operands.contains(operand) {
+                        operands.push(operand.clone());
+                    }
+                }
+            }
+        }
+    }
+
+    let contradicts = |c: &Term| {
+        let never_unknown = match c {
+            Term::IsNull(_) => true,
+            _ => false,
+        };
+        never_unknown && operands.iter().any(|other| matches_not(other, c))
+    };
+    if operands.iter().any(contradicts) {
+        return Term::Bool(!and);
+    }
+
+    // Outside of boolean position, a chain reduced to a single operand
+    // would yield that operand's value instead of its truthiness
+    if !boolean && operands.len() == 1 && !is_boolean(&operands[0]) {
+        let operands = chain_operands(t, and)
+            .into_iter()
+            .map(|operand| simplify_in(operand, true));
+        return join_chain(operands.collect(), and);
+    }
+    join_chain(operands, and)
+}
+
+/// The chain of ```and``` (if ```and``` is set) or ```or``` of
+/// ```operands```
+fn join_chain<'a>(operands: Vec<Term<'a>>, and: bool) -> Term<'a> {
+    let mut operands = operands.into_iter();
+    let first = match operands.next() {
+        Some(first) => first,
+        None => return Term::Bool(and),
+    };
+    operands.fold(first, |left, right| {
+        if and {
+            Term::And(Box::new(left), Box::new(right))
+        } else {
+            Term::Or(Box::new(left), Box::new(right))
+        }
+    })
+}
+
+/// The operands of a left associative chain of ```and``` or ```or```
+fn chain_operands<'t, 'a>(t: &'t Term<'a>, and: bool) -> Vec<&'t Term<'a>> {
+    match (t, and) {
+        (Term::And(left, right), true) | (Term::Or(left, right), false) => {
+            let mut operands = chain_operands(left, and);
+            operands.extend(chain_operands(right, and));
+            operands
+        }
+        _ => vec![t],
+    }
+}
+
+/// Whether ```t``` is ```not c```
+fn matches_not(t: &Term, c: &Term) -> bool {
+    match t {
+        Term::Not(inner) => **inner == *c,
+        _ => false,
+    }
+}
+
+fn is_comparison(t: &Term) -> bool {
+    match t {
+        Term::Eq(_, _)
+        | Term::Lt(_, _)
+        | Term::Le(_, _)
+        | Term::Gt(_, _)
+        | Term::Ge(_, _)
+        | Term::Matches(_, _)
+        | Term::In(_, _)
+        | Term::IsNull(_) => true,
+        _ => false,
+    }
+}
+
+/// Whether ```t``` always evaluates to a boolean
+fn is_boolean(t: &Term) -> bool {
+    match t {
+        Term::Bool(_) | Term::Not(_) | Term::And(_, _) | Term::Or(_, _) => true,
+        _ => is_comparison(t),
+    }
+}
+
+/// The result of the comparison ```t``` of the literals ```left```
+/// and ```right```, or None if either is not a literal. The rules
+/// are those of the interpreter backend. Literals of types that
+/// cannot be compared are left for the type checker to reject.
+fn fold_comparison(t: &Term, left: &Term, right: &Term) -> Option<bool> {
+    if !is_literal(left) || !is_literal(right) {
+        return None;
+    }
+    let order = order(left, right);
+    match t {
+        Term::Eq(_, _) if equatable(left, right) => Some(literals_equal(left, right)),
+        Term::Matches(_, _) => match (left, right) {
+            // Leave invalid regular expressions to the compiler to
+            // report
+            (Term::String(s), Term::Regex(re, flags)) => {
+                flags.build(re).ok().map(|re| re.is_match(s))
+            }
+            _ => None,
+        },
+        Term::Lt(_, _) | Term::Le(_, _) | Term::Gt(_, _) | Term::Ge(_, _)
+            if !orderable(left, right) =>
+        {
+            None
+        }
+        // Only NaN is unordered, and comparisons with it are false
+        Term::Lt(_, _) => Some(order == Some(Ordering::Less)),
+        Term::Le(_, _) => Some(order.map(|o| o != Ordering::Greater).unwrap_or(false)),
+        Term::Gt(_, _) => Some(order == Some(Ordering::Greater)),
+        Term::Ge(_, _) => Some(order.map(|o| o != Ordering::Less).unwrap_or(false)),
+        _ => None,
+    }
+}
+
+/// Whether the literals ```left``` and ```right``` can be compared
+/// with ```<``` and the like: both are numbers, or both are strings
+fn orderable(left: &Term, right: &Term) -> bool {
+    match (left, right) {
+        (Term::Int(_), Term::Int(_))
+        | (Term::Int(_), Term::Float(_))
+        | (Term::Float(_), Term::Int(_))
+        | (Term::Float(_), Term::Float(_))
+        | (Term::String(_), Term::String(_)) => true,
+        _ => false,
+    }
+}
+
+/// Whether the literals ```left``` and ```right``` can be compared
+/// with ```==``` and ```in```
+fn equatable(left: &Term, right: &Term) -> bool {
+    match (left, right) {
+        (Term::Bool(_), Term::Bool(_)) => true,
+        _ => orderable(left, right),
+    }
+}
+
+/// Whether the literals ```left``` and ```right``` are equal
+fn literals_equal(left: &Term, right: &Term) -> bool {
+    match (left, right) {
+        (Term::Bool(l), Term::Bool(r)) => l == r,
+        _ => order(left, right) == Some(Ordering::Equal),
+    }
+}
+
+/// The result of ```left op right```, or None if they are not both
+/// numeric literals or the result is an error
+fn fold_arith<'a>(op: Arith, left: &Term, right: &Term) -> Option<Term<'a>> {
+    match (left, right) {
+        (Term::Int(l), Term::Int(r)) => op.apply_i64(*l, *r).ok().map(Term::Int),
+        (Term::Int(l), Term::Float(r)) => Some(Term::Float(op.apply_f64(*l as f64, *r))),
+        (Term::Float(l), Term::Int(r)) => Some(Term::Float(op.apply_f64(*l, *r as f64))),
+        (Term::Float(l), Term::Float(r)) => Some(Term::Float(op.apply_f64(*l, *r))),
+        _ => None,
+    }
+}
+
+fn is_literal(t: &Term) -> bool {
+    match t {
+        Term::Bool(_) | Term::Int(_) | Term::Float(_) | Term::String(_) | Term::Regex(..) => true,
+        _ => false,
+    }
+}
+
+fn order(left: &Term, right: &Term) -> Option<Ordering> {
+    match (left, right) {
+        (Term::Int(l), Term::Int(r)) => Some(l.cmp(r)),
+        (Term::Float(l), Term::Float(r)) => l.partial_cmp(r),
+        (Term::Int(l), Term::Float(r)) => (*l as f64).partial_cmp(r),
+        (Term::Float(l), Term::Int(r)) => l.partial_cmp(&(*r as f64)),
+        (Term::String(l), Term::String(r)) => Some(l.as_bytes().cmp(r.as_bytes())),
+        _ => None,
+    }
+}
@@ -1,11 +1,18 @@
+use regex::{Regex, RegexBuilder};
+use std::borrow::Cow;
+use std::fmt;
+
+use crate::error::{Error, Result};
+
 /// A compiled term represented as an abstract syntax tree.
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Term<'a> {
+    Bool(bool),
     Int(i64),
     Float(f64),
-    String(&'a str),
-    Regex(&'a str),
-    Variable(&'a str),
+    String(Cow<'a, str>),
+    Regex(Cow<'a, str>, RegexFlags),
+    Variable(Cow<'a, str>),
     LoadedTerm(i64),
     Not(Box<Term<'a>>),
     Eq(Box<Term<'a>>, Box<Term<'a>>),
@@ -15,4 +22,368 @@ pub enum Term<'a> {
     Le(Box<Term<'a>>, Box<Term<'a>>),
     Gt(Box<Term<'a>>, Box<Term<'a>>),
     Ge(Box<Term<'a>>, Box<Term<'a>>),
+    /// Whether the text on the left matches the regex on the right
+    Matches(Box<Term<'a>>, Box<Term<'a>>),
+    /// Whether the value on the left equals one of the literals
+    In(Box<Term<'a>>, Vec<Term<'a>>),
+    /// Whether the value is null, e.g. a variable that was not set
+    IsNull(Box<Term<'a>>),
+    Add(Box<Term<'a>>, Box<Term<'a>>),
+    Sub(Box<Term<'a>>, Box<Term<'a>>),
+    Mul(Box<Term<'a>>, Box<Term<'a>>),
+    Div(Box<Term<'a>>, Box<Term<'a>>),
+    Mod(Box<Term<'a>>, Box<Term<'a>>),
+    Neg(Box<Term<'a>>),
+}
+
+/// Flags of a regex literal, written after its closing slash, e.g.
+/// ```/^yes$/im```
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct RegexFlags {
+    /// ```i```: letters match regardless of their case
+    pub case_insensitive: bool,
+    /// ```m```: ```^``` and ```$``` match at the start and end of lines
+    pub multi_line: bool,
+    /// ```s```: ```.``` also matches ```\n```
+    pub dot_matches_new_line: bool,
+    /// ```x```: whitespace in the pattern is ignored and ```#``` starts
+    /// a comment
+    pub ignore_whitespace: bool,
+}
+
+impl RegexFlags {
+    /// The flags in the order they are written
+    const LETTERS: [char; 4] = ['i', 'm', 's', 'x'];
+
+    fn flags(self) -> [bool; 4] {
+        [
+            self.case_insensitive,
+            self.multi_line,
+            self.dot_matches_new_line,
+            self.ignore_whitespace,
+        ]
+    }
+
+    /// Set the flag written as ```letter```. Returns false if there
+    /// is no such flag.
+    pub fn set(&mut self, letter: char) -> bool {
+        let flag = match letter {
+            'i' => &mut self.case_insensitive,
+            'm' => &mut self.multi_line,
+            's' => &mut self.dot_matches_new_line,
+            'x' => &mut self.ignore_whitespace,
+            _ => return false,
+        };
+        *flag = true;
+        true
+    }
+
+    /// The flags as passed to wormrtl's ```make_regex```: bit 0 is
+    /// ```i```, bit 1 ```m```, bit 2 ```s``` and bit 3 ```x```.
+    pub(crate) fn bits(self) -> i64 {
+        self.flags()
+            .iter()
+            .enumerate()
+            .filter(|(_, set)| **set)
+            .fold(0, |bits, (bit, _)| bits | 1 << bit)
+    }
+
+    /// Compile ```pattern``` with these flags
+    pub(crate) fn build(self, pattern: &str) -> Result<Regex> {
+        RegexBuilder::new(pattern)
+            .case_insensitive(self.case_insensitive)
+            .multi_line(self.multi_line)
+            .dot_matches_new_line(self.dot_matches_new_line)
+            .ignore_whitespace(self.ignore_whitespace)
+            .build()
+            .map_err(|e| Error::InvalidRegex(format!("/{}/{}: {}", pattern, self, e)))
+    }
+}
+
+impl fmt::Display for RegexFlags {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (letter, set) in Self::LETTERS.iter().zip(self.flags().iter()) {
+            if *set {
+                write!(f, "{}", letter)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The binary arithmetic operators
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Arith {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+}
+
+impl Arith {
+    /// The operator of ```t``` along with its operands, if ```t``` is
+    /// a binary arithmetic term
+    pub(crate) fn of<'t, 'a>(t: &'t Term<'a>) -> Option<(Arith, &'t Term<'a>, &'t Term<'a>)> {
+        match t {
+            Term::Add(t1, t2) => Some((Arith::Add, t1, t2)),
+            Term::Sub(t1, t2) => Some((Arith::Sub, t1, t2)),
+            Term::Mul(t1, t2) => Some((Arith::Mul, t1, t2)),
+            Term::Div(t1, t2) => Some((Arith::Div, t1, t2)),
+            Term::Mod(t1, t2) => Some((Arith::Mod, t1, t2)),
+            _ => None,
+        }
+    }
+
+    /// Build the term applying this operator to ```t1``` and ```t2```
+    pub(crate) fn make<'a>(self, t1: Term<'a>, t2: Term<'a>) -> Term<'a> {
+        let (t1, t2) = (Box::new(t1), Box::new(t2));
+        match self {
+            Arith::Add => Term::Add(t1, t2),
+            Arith::Sub => Term::Sub(t1, t2),
+            Arith::Mul => Term::Mul(t1, t2),
+            Arith::Div => Term::Div(t1, t2),
+            Arith::Mod => Term::Mod(t1, t2),
+        }
+    }
+
+    pub(crate) fn symbol(self) -> &'static str {
+        match self {
+            Arith::Add => "+",
+            Arith::Sub => "-",
+            Arith::Mul => "*",
+            Arith::Div => "/",
+            Arith::Mod => "%",
+        }
+    }
+
+    /// Integer arithmetic wraps around on overflow, division truncates
+    /// towards zero. Division and remainder by zero fail.
+    pub(crate) fn apply_i64(self, l: i64, r: i64) -> Result<i64> {
+        match self {
+            Arith::Add => Ok(l.wrapping_add(r)),
+            Arith::Sub => Ok(l.wrapping_sub(r)),
+            Arith::Mul => Ok(l.wrapping_mul(r)),
+            Arith::Div | Arith::Mod if r == 0 => Err(Error::DivisionByZero),
+            Arith::Div => Ok(l.wrapping_div(r)),
+            Arith::Mod => Ok(l.wrapping_rem(r)),
+        }
+    }
+
+    /// Float arithmetic follows IEEE 754. The remainder is computed as
+    /// ```l - r * trunc(l / r)```, as webassembly has no instruction
+    /// for it.
+    pub(crate) fn apply_f64(self, l: f64, r: f64) -> f64 {
+        match self {
+            Arith::Add => l + r,
+            Arith::Sub => l - r,
+            Arith::Mul => l * r,
+            Arith::Div => l / r,
+            Arith::Mod => l - r * (l / r).trunc(),
+        }
+    }
+}
+
+impl<'a> Term<'a> {
+    /// Copy all text borrowed from the formula source, so that the
+    /// term can outlive it, e.g. to be kept along with the formulas
+    /// compiled from it.
+    pub fn into_owned(self) -> Term<'static> {
+        let owned = |t: Box<Term<'a>>| Box::new(t.into_owned());
+        match self {
+            Term::Bool(bval) => Term::Bool(bval),
+            Term::Int(intval) => Term::Int(intval),
+            Term::Float(fval) => Term::Float(fval),
+            Term::String(string) => Term::String(Cow::Owned(string.into_owned())),
+            Term::Regex(pattern, flags) => Term::Regex(Cow::Owned(pattern.into_owned()), flags),
+            Term::Variable(var_name) => Term::Variable(Cow::Owned(var_name.into_owned())),
+            Term::LoadedTerm(idx) => Term::LoadedTerm(idx),
+            Term::Not(t) => Term::Not(owned(t)),
+            Term::Eq(t1, t2) => Term::Eq(owned(t1), owned(t2)),
+            Term::Or(t1, t2) => Term::Or(owned(t1), owned(t2)),
+            Term::And(t1, t2) => Term::And(owned(t1), owned(t2)),
+            Term::Lt(t1, t2) => Term::Lt(owned(t1), owned(t2)),
+            Term::Le(t1, t2) => Term::Le(owned(t1), owned(t2)),
+            Term::Gt(t1, t2) => Term::Gt(owned(t1), owned(t2)),
+            Term::Ge(t1, t2) => Term::Ge(owned(t1), owned(t2)),
+            Term::Matches(t1, t2) => Term::Matches(owned(t1), owned(t2)),
+            Term::In(t, list) => Term::In(
+                owned(t),
+                list.into_iter()
+                    .map(|element| element.into_owned())
+                    .collect(),
+            ),
+            Term::IsNull(t) => Term::IsNull(owned(t)),
+            Term::Add(t1, t2) => Term::Add(owned(t1), owned(t2)),
+            Term::Sub(t1, t2) => Term::Sub(owned(t1), owned(t2)),
+            Term::Mul(t1, t2) => Term::Mul(owned(t1), owned(t2)),
+            Term::Div(t1, t2) => Term::Div(owned(t1), owned(t2)),
+            Term::Mod(t1, t2) => Term::Mod(owned(t1), owned(t2)),
+            Term::Neg(t) => Term::Neg(owned(t)),
+        }
+    }
+
+    /// How tightly the term's operator binds, from ```or``` (loosest)
+    /// to literals and variables (tightest)
+    fn precedence(&self) -> u8 {
+        match self {
+            Term::Or(_, _) => 1,
+            Term::And(_, _) => 2,
+            Term::Eq(_, _)
+            | Term::Lt(_, _)
+            | Term::Le(_, _)
+            | Term::Gt(_, _)
+            | Term::Ge(_, _)
+            | Term::Matches(_, _)
+            | Term::In(_, _)
+            | Term::IsNull(_) => 3,
+            Term::Add(_, _) | Term::Sub(_, _) => 4,
+            Term::Mul(_, _) | Term::Div(_, _) | Term::Mod(_, _) => 5,
+            Term::Not(inner) => match **inner {
+                // Printed as !=, !~, not in and is not null
+                Term::Eq(_, _) | Term::Matches(_, _) | Term::In(_, _) | Term::IsNull(_) => 3,
+                _ => 6,
+            },
+            Term::Neg(_) => 6,
+            _ => 7,
+        }
+    }
+
+    /// Write ```self```, parenthesized if its operator binds looser
+    /// than ```min```
+    fn fmt_operand(&self, f: &mut fmt::Formatter, min: u8) -> fmt::Result {
+        if self.precedence() < min {
+            write!(f, "({})", self)
+        } else {
+            write!(f, "{}", self)
+        }
+    }
+
+    fn fmt_binary(&self, f: &mut fmt::Formatter, t1: &Term, op: &str, t2: &Term) -> fmt::Result {
+        let p = self.precedence();
+        t1.fmt_operand(f, p)?;
+        write!(f, " {} ", op)?;
+        t2.fmt_operand(f, p + 1)
+    }
+
+    fn fmt_in(
+        &self,
+        f: &mut fmt::Formatter,
+        subject: &Term,
+        op: &str,
+        list: &[Term],
+    ) -> fmt::Result {
+        subject.fmt_operand(f, self.precedence())?;
+        write!(f, " {} (", op)?;
+        for (i, element) in list.iter().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            write!(f, "{}", element)?;
+        }
+        f.write_str(")")
+    }
+
+    fn fmt_is_null(&self, f: &mut fmt::Formatter, subject: &Term, op: &str) -> fmt::Result {
+        subject.fmt_operand(f, self.precedence())?;
+        write!(f, " {}", op)
+    }
+}
+
+/// Write ```s``` as a double quoted string literal, escaping quotes,
+/// backslashes and control characters
+fn fmt_string(f: &mut fmt::Formatter, s: &str) -> fmt::Result {
+    f.write_str("\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\t' => f.write_str("\\t")?,
+            '\r' => f.write_str("\\r")?,
+            '\0' => f.write_str("\\0")?,
+            c if c.is_control() => write!(f, "\\u{{{:x}}}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    f.write_str("\"")
+}
+
+/// Write ```pattern``` as a regex literal, escaping slashes
+fn fmt_regex(f: &mut fmt::Formatter, pattern: &str, flags: RegexFlags) -> fmt::Result {
+    f.write_str("/")?;
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            // Keep escape sequences, e.g. \\, intact
+            '\\' => {
+                f.write_str("\\")?;
+                if let Some(escaped) = chars.next() {
+                    write!(f, "{}", escaped)?;
+                }
+            }
+            '/' => f.write_str("\\/")?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "/{}", flags)
+}
+
+/// Formats the term in formula syntax, parenthesized as needed so
+/// that parsing the output yields the same term. NaN has no literal:
+/// it is written as ```(0.0 / 0.0)```, which parses to a division
+/// that evaluates to NaN rather than to the NaN literal itself.
+impl<'a> fmt::Display for Term<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Term::Bool(bval) => write!(f, "{}", bval),
+            Term::Int(intval) => write!(f, "{}", intval),
+            // There are no literals for infinities and NaN, which the
+            // simplifier may produce, so they are written as values
+            // that evaluate to them
+            Term::Float(fval) if fval.is_nan() => write!(f, "(0.0 / 0.0)"),
+            Term::Float(fval) if fval.is_infinite() => {
+                write!(f, "{}1e999", if *fval < 0.0 { "-" } else { "" })
+            }
+            // Debug keeps the fraction of integral floats, e.g. 1.0
+            Term::Float(fval) => write!(f, "{:?}", fval),
+            Term::String(sval) => fmt_string(f, sval),
+            Term::Regex(rval, flags) => fmt_regex(f, rval, *flags),
+            Term::Variable(var_name) => write!(f, "{}", var_name),
+            Term::LoadedTerm(idx) => write!(f, "<loaded term {}>", idx),
+            Term::Not(inner) => match &**inner {
+                Term::Eq(t1, t2) => self.fmt_binary(f, t1, "!=", t2),
+                Term::Matches(t1, t2) => self.fmt_binary(f, t1, "!~", t2),
+                Term::In(subject, list) => self.fmt_in(f, subject, "not in", list),
+                Term::IsNull(subject) => self.fmt_is_null(f, subject, "is not null"),
+                _ => {
+                    f.write_str("not ")?;
+                    inner.fmt_operand(f, 6)
+                }
+            },
+            Term::Eq(t1, t2) => self.fmt_binary(f, t1, "==", t2),
+            Term::Or(t1, t2) => self.fmt_binary(f, t1, "or", t2),
+            Term::And(t1, t2) => self.fmt_binary(f, t1, "and", t2),
+            Term::Lt(t1, t2) => self.fmt_binary(f, t1, "<", t2),
+            Term::Le(t1, t2) => self.fmt_binary(f, t1, "<=", t2),
+            Term::Gt(t1, t2) => self.fmt_binary(f, t1, ">", t2),
+            Term::Ge(t1, t2) => self.fmt_binary(f, t1, ">=", t2),
+            Term::Matches(t1, t2) => self.fmt_binary(f, t1, "=~", t2),
+            Term::In(subject, list) => self.fmt_in(f, subject, "in", list),
+            Term::IsNull(subject) => self.fmt_is_null(f, subject, "is null"),
+            Term::Add(t1, t2) => self.fmt_binary(f, t1, "+", t2),
+            Term::Sub(t1, t2) => self.fmt_binary(f, t1, "-", t2),
+            Term::Mul(t1, t2) => self.fmt_binary(f, t1, "*", t2),
+            Term::Div(t1, t2) => self.fmt_binary(f, t1, "/", t2),
+            Term::Mod(t1, t2) => self.fmt_binary(f, t1, "%", t2),
+            Term::Neg(inner) => {
+                f.write_str("-")?;
+                match &**inner {
+                    // -5 would be parsed as a single literal
+                    Term::Int(_) | Term::Float(_) => write!(f, "({})", inner),
+                    _ => inner.fmt_operand(f, 6),
+                }
+            }
+        }
+    }
 }
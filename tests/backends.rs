@@ -0,0 +1,716 @@
+extern crate wormula;
+
+use std::collections::HashMap;
+use std::time::{Duration, UNIX_EPOCH};
+
+use wormula::evaluator::{Backend, Column, Context, InstantiatedTerm, Type, Variable};
+use wormula::parse::parse_formula;
+use wormula::Error;
+
+/// Evaluate ```formula``` for every (x, name) pair with ```backend```
+fn eval_all(backend: Backend, formula: &str, rows: &[(f64, &str)]) -> Vec<bool> {
+    let mut context = Context::with_backend(backend).unwrap();
+    context.define_var("x");
+    context.define_var("name");
+    let term = parse_formula(formula).unwrap();
+    let compiled = context.compile(&term).unwrap();
+    let instance = compiled.instantiate().unwrap();
+    let mut x = instance.get_variable("x").unwrap();
+    let mut name = instance.get_variable("name").unwrap();
+    rows.iter()
+        .map(|(xv, namev)| {
+            x.set_f64(*xv).unwrap();
+            name.set_string(namev).unwrap();
+            instance.run().unwrap()
+        })
+        .collect()
+}
+
+static ROWS: &[(f64, &str)] = &[
+    (0.0, "Yvonne"),
+    (1.0, "Hans"),
+    (250.0, "Yuki"),
+    (251.0, "Alice"),
+    (-3.5, ""),
+];
+
+static FORMULAS: &[&str] = &[
+    "x == 1",
+    "x != 1",
+    "x > 250",
+    "x >= 250 and name =~ /^Y/",
+    "x < 1 or name == \"Hans\"",
+    "not (x <= 0) and name > \"B\"",
+    "(x and name) == true",
+];
+
+#[test]
+fn interpreter_evaluates_formulas() {
+    let results: Vec<Vec<bool>> = FORMULAS
+        .iter()
+        .map(|f| eval_all(Backend::Interpreter, f, ROWS))
+        .collect();
+    assert_eq!(results[0], vec![false, true, false, false, false]);
+    assert_eq!(results[1], vec![true, false, true, true, true]);
+    assert_eq!(results[2], vec![false, false, false, true, false]);
+    assert_eq!(results[3], vec![false, false, true, false, false]);
+    assert_eq!(results[4], vec![true, true, false, false, true]);
+    assert_eq!(results[5], vec![false, true, true, false, false]);
+    assert_eq!(results[6], vec![false, true, true, true, false]);
+}
+
+#[test]
+fn backends_agree() {
+    for formula in FORMULAS {
+        assert_eq!(
+            eval_all(Backend::Interpreter, formula, ROWS),
+            eval_all(Backend::Wasm, formula, ROWS),
+            "{}",
+            formula
+        );
+    }
+}
+
+#[test]
+fn batches_of_every_column_type() {
+    for backend in &[Backend::Interpreter, Backend::Wasm] {
+        let mut context = Context::with_backend(*backend).unwrap();
+        context.define_var_typed("admin", Type::Bool);
+        context.define_var_typed("created", Type::Timestamp);
+        context.define_var_typed("since", Type::Timestamp);
+        context.define_var_typed("payload", Type::Bytes);
+        context.define_var_typed("score", Type::F64);
+        context.define_var("flag");
+        let term = parse_formula(
+            "admin and created > since and payload == 'ok' and flag == 1 and score > 1",
+        )
+        .unwrap();
+        let compiled = context.compile(&term).unwrap();
+        let instance = compiled.instantiate().unwrap();
+        let mut since = instance.get_typed_variable("since").unwrap();
+        since.set(UNIX_EPOCH + Duration::from_secs(1)).unwrap();
+
+        let seconds = |s| UNIX_EPOCH + Duration::from_secs(s);
+        let scores = Column::F64(&[2.0, 2.0, 2.0, 0.5]);
+        let results = instance
+            .run_batch(&[
+                ("admin", Column::Bool(&[true, true, true, true])),
+                (
+                    "created",
+                    Column::Timestamp(&[seconds(2), seconds(0), seconds(2), seconds(2)]),
+                ),
+                ("payload", Column::Bytes(&[b"ok", b"ok", b"ok", b"ok"])),
+                ("flag", Column::Bool(&[true, true, true, true])),
+                (
+                    "score",
+                    Column::Nullable(&scores, &[false, false, true, false]),
+                ),
+            ])
+            .unwrap();
+        assert_eq!(results, vec![true, false, false, false], "{:?}", backend);
+
+        // Null masks must have a byte per row
+        let mask = [false, true];
+        match instance.run_batch(&[("score", Column::Nullable(&scores, &mask[..]))]) {
+            Err(Error::InvalidInput(_)) => {}
+            other => panic!("{:?}: expected invalid input, got {:?}", backend, other),
+        }
+
+        // The last row stays assigned, including its nulls
+        let results = instance
+            .run_batch(&[(
+                "score",
+                Column::Nullable(&Column::F64(&[3.0, 3.0]), &mask[..]),
+            )])
+            .unwrap();
+        assert_eq!(results, vec![true, false], "{:?}", backend);
+        assert_eq!(instance.evaluate(), Ok(None), "{:?}", backend);
+    }
+}
+
+/// Formulas whose right operand fails to evaluate when x is 1, as a
+/// string is not a boolean. They only succeed if it is skipped.
+/// Formulas on x, which is 1, whose right operands would divide the
+/// typed variable n by zero if they were evaluated
+static SHORT_CIRCUIT: &[(&str, bool)] = &[
+    ("x == 1 or n / 0 == 1", true),
+    ("x != 1 and n / 0 == 1", false),
+    ("not (x != 1 and n / 0 == 1)", true),
+    ("(x == 1 or n / 0 == 1) == (x == 1)", true),
+];
+
+#[test]
+fn connectives_short_circuit() {
+    for backend in &[Backend::Interpreter, Backend::Wasm] {
+        let mut context = Context::with_backend(*backend).unwrap();
+        context.define_var("x");
+        context.define_var_typed("n", Type::I64);
+        for (formula, expected) in SHORT_CIRCUIT {
+            let term = parse_formula(formula).unwrap();
+            let compiled = context.compile(&term).unwrap();
+            let instance = compiled.instantiate().unwrap();
+            instance.get_variable("x").unwrap().set_f64(1.0).unwrap();
+            instance
+                .get_typed_variable::<i64>("n")
+                .unwrap()
+                .set(7)
+                .unwrap();
+            assert_eq!(instance.run(), Ok(*expected), "{:?}: {}", backend, formula);
+        }
+        // Evaluating the right operand does fail
+        let term = parse_formula("x != 1 or n / 0 == 1").unwrap();
+        let instance = context.compile(&term).unwrap().instantiate().unwrap();
+        instance.get_variable("x").unwrap().set_f64(1.0).unwrap();
+        instance
+            .get_typed_variable::<i64>("n")
+            .unwrap()
+            .set(7)
+            .unwrap();
+        assert_eq!(instance.run(), Err(Error::DivisionByZero), "{:?}", backend);
+    }
+}
+
+/// Evaluate ```formula``` with the typed variables ```n``` (I64) and
+/// ```r``` (F64)
+fn eval_typed(backend: Backend, formula: &str, n: i64, r: f64) -> wormula::Result<bool> {
+    let mut context = Context::with_backend(backend).unwrap();
+    context.define_var_typed("n", Type::I64);
+    context.define_var_typed("r", Type::F64);
+    let term = parse_formula(formula).unwrap();
+    let compiled = context.compile(&term)?;
+    let instance = compiled.instantiate()?;
+    instance.get_typed_variable::<i64>("n")?.set(n)?;
+    instance.get_typed_variable::<f64>("r")?.set(r)?;
+    instance.run()
+}
+
+static ARITHMETIC: &[(&str, bool)] = &[
+    ("n * 3 + 1 == 22", true),
+    ("(n + n) / n == 2", true),
+    ("n % 4 == 3", true),
+    ("-n == 0 - 7", true),
+    ("r / 2 == 1.25", true),
+    ("n + r == 9.5", true),
+    ("r % 1 == 0.5", true),
+    ("n - 8 < 0 and n * n >= 49", true),
+    ("n * n / n == n + r", false),
+    ("n + 9223372036854775807 == -9223372036854775802", true),
+    ("n + 9_007_199_254_740_986 == 9007199254740993", true),
+];
+
+#[test]
+fn arithmetic_on_typed_variables() {
+    for backend in &[Backend::Interpreter, Backend::Wasm] {
+        for (formula, expected) in ARITHMETIC {
+            assert_eq!(
+                eval_typed(*backend, formula, 7, 2.5),
+                Ok(*expected),
+                "{:?}: {}",
+                backend,
+                formula
+            );
+        }
+    }
+}
+
+#[test]
+fn integer_division_by_zero_fails() {
+    for backend in &[Backend::Interpreter, Backend::Wasm] {
+        for formula in &["n / (n - n) == 1", "n % (n - n) == 1"] {
+            assert_eq!(
+                eval_typed(*backend, formula, 7, 2.5),
+                Err(Error::DivisionByZero),
+                "{:?}: {}",
+                backend,
+                formula
+            );
+        }
+        // Float division follows IEEE 754
+        assert_eq!(eval_typed(*backend, "r / 0 > 1000", 7, 2.5), Ok(true));
+    }
+}
+
+#[test]
+fn arithmetic_on_untyped_variables() {
+    for backend in &[Backend::Interpreter, Backend::Wasm] {
+        let mut context = Context::with_backend(*backend).unwrap();
+        context.define_var("x");
+        context.define_var_typed("n", Type::I64);
+        let run = |formula: &str, set: &dyn Fn(&mut Variable)| {
+            let term = parse_formula(formula).unwrap();
+            let compiled = context.compile(&term).unwrap();
+            let instance = compiled.instantiate().unwrap();
+            instance
+                .get_typed_variable::<i64>("n")
+                .unwrap()
+                .set(2)
+                .unwrap();
+            set(&mut instance.get_variable("x").unwrap());
+            instance.run()
+        };
+        let int = |v: &mut Variable| v.set_i64(7).unwrap();
+        let float = |v: &mut Variable| v.set_f64(2.5).unwrap();
+        let text = |v: &mut Variable| v.set_string("7").unwrap();
+
+        for (formula, expected) in &[
+            ("x * 3 + 1 == 22", true),
+            ("x / n == 3 and x % n == 1", true),
+            ("-x < 0 and x - 8 in (-1, 3)", true),
+            ("x + 9223372036854775807 == -9223372036854775802", true),
+            ("x + 9007199254740986 in (9007199254740992, 0.5)", false),
+        ] {
+            assert_eq!(
+                run(formula, &int),
+                Ok(*expected),
+                "{:?}: {}",
+                backend,
+                formula
+            );
+        }
+        for (formula, expected) in &[
+            ("x * 2 == 5", true),
+            ("x + n > 4.4 and x % 1 == 0.5", true),
+            ("x * n in (5, 6)", true),
+        ] {
+            assert_eq!(
+                run(formula, &float),
+                Ok(*expected),
+                "{:?}: {}",
+                backend,
+                formula
+            );
+        }
+        assert_eq!(
+            run("x / (x - x) == 1", &int),
+            Err(Error::DivisionByZero),
+            "{:?}",
+            backend
+        );
+        match run("x + 1 == 8", &text) {
+            Err(Error::TypeMismatch(_)) => {}
+            other => panic!("{:?}: expected a type mismatch, got {:?}", backend, other),
+        }
+    }
+}
+
+/// Bare variables in boolean position, evaluated for x and name
+static TRUTHINESS: &[(&str, f64, &str, bool)] = &[
+    ("x", 0.0, "", false),
+    ("x", -1.5, "", true),
+    ("name", 0.0, "", false),
+    ("name", 0.0, "Hans", true),
+    ("!x and name", 0.0, "Hans", true),
+    ("x or true", 0.0, "", true),
+    ("not (x or false)", 0.0, "", true),
+];
+
+#[test]
+fn bare_variables_are_truthy() {
+    for backend in &[Backend::Interpreter, Backend::Wasm] {
+        for (formula, x, name, expected) in TRUTHINESS {
+            assert_eq!(
+                eval_all(*backend, formula, &[(*x, *name)]),
+                vec![*expected],
+                "{:?}: {} with x = {}, name = {:?}",
+                backend,
+                formula,
+                x,
+                name
+            );
+        }
+    }
+}
+
+#[test]
+fn untyped_variables_can_be_set_to_booleans() {
+    for backend in &[Backend::Interpreter, Backend::Wasm] {
+        let mut context = Context::with_backend(*backend).unwrap();
+        context.define_var("flag");
+        context.define_var_typed("typed", Type::Bool);
+        let term = parse_formula("flag and not typed").unwrap();
+        let compiled = context.compile(&term).unwrap();
+        let instance = compiled.instantiate().unwrap();
+        let mut flag = instance.get_variable("flag").unwrap();
+        let mut typed = instance.get_typed_variable::<bool>("typed").unwrap();
+        flag.set_bool(true).unwrap();
+        typed.set(false).unwrap();
+        assert_eq!(instance.run(), Ok(true), "{:?}", backend);
+        flag.set_bool(false).unwrap();
+        assert_eq!(instance.run(), Ok(false), "{:?}", backend);
+    }
+}
+
+/// Membership tests, evaluated for x and name
+static MEMBERSHIP: &[(&str, f64, &str, bool)] = &[
+    ("name in ('Hans', 'Yuki')", 0.0, "Yuki", true),
+    ("name in ('Hans', 'Yuki')", 0.0, "Yvonne", false),
+    (
+        "name not in ('a', 'b', 'c', 'd', 'e', 'f', 'g')",
+        0.0,
+        "e",
+        false,
+    ),
+    (
+        "name not in ('a', 'b', 'c', 'd', 'e', 'f', 'g')",
+        0.0,
+        "h",
+        true,
+    ),
+    ("x in (1, 2, 3, 5, 8, 13, 21, 34)", 13.0, "", true),
+    ("x in (34, 21, 13, 8, 5, 3, 2, 1)", 12.0, "", false),
+    ("x in (1, 2.5)", 2.5, "", true),
+    ("x in ('1', 1)", 1.0, "", true),
+    ("name in ('1', 1)", 0.0, "1", true),
+    ("name in (1, 2, 3, 4, 5, 6)", 0.0, "4", false),
+    ("(x in (1, 2)) == (name in ('a'))", 3.0, "b", true),
+];
+
+#[test]
+fn membership_in_literal_lists() {
+    for backend in &[Backend::Interpreter, Backend::Wasm] {
+        for (formula, x, name, expected) in MEMBERSHIP {
+            assert_eq!(
+                eval_all(*backend, formula, &[(*x, *name)]),
+                vec![*expected],
+                "{:?}: {} with x = {}, name = {:?}",
+                backend,
+                formula,
+                x,
+                name
+            );
+        }
+    }
+}
+
+#[test]
+fn membership_of_typed_variables() {
+    for backend in &[Backend::Interpreter, Backend::Wasm] {
+        for (formula, expected) in &[
+            ("n in (1, 3, 5, 7, 9, 11)", true),
+            ("n in (2, 4, 6, 8, 10, 12)", false),
+            ("n + r in (9, 9.5, 10)", true),
+            ("r not in (2, 3, 4, 5, 6)", true),
+            ("n in (9007199254740993, 7)", true),
+            // Integers are not rounded to f64 to compare them with integers
+            ("9007199254740993 in (9007199254740992, 0.5)", false),
+            ("n + 9007199254740986 in (9007199254740992, 0.5)", false),
+            ("n + 9007199254740986 in (9007199254740993, 0.5)", true),
+        ] {
+            assert_eq!(
+                eval_typed(*backend, formula, 7, 2.5),
+                Ok(*expected),
+                "{:?}: {}",
+                backend,
+                formula
+            );
+        }
+    }
+}
+
+#[test]
+fn untyped_booleans_compare_like_integers() {
+    for backend in &[Backend::Interpreter, Backend::Wasm] {
+        let mut context = Context::with_backend(*backend).unwrap();
+        context.define_var("flag");
+        for formula in &["flag == true", "flag != false", "flag in (1, true)"] {
+            match context.compile(&parse_formula(formula).unwrap()) {
+                Err(Error::TypeMismatch(message)) => assert!(message.contains("Bool")),
+                _ => panic!("{}: expected a type mismatch ({:?})", formula, backend),
+            }
+        }
+
+        let term = parse_formula("flag == 1 and flag > 0 and flag").unwrap();
+        let compiled = context.compile(&term).unwrap();
+        let instance = compiled.instantiate().unwrap();
+        let mut flag = instance.get_variable("flag").unwrap();
+        flag.set_bool(true).unwrap();
+        assert_eq!(instance.run(), Ok(true), "{:?}", backend);
+        flag.set_bool(false).unwrap();
+        assert_eq!(instance.run(), Ok(false), "{:?}", backend);
+    }
+}
+
+#[test]
+fn regex_flags_apply() {
+    for backend in &[Backend::Interpreter, Backend::Wasm] {
+        for (formula, expected) in &[
+            ("name =~ /^yvonne$/", vec![false, false]),
+            ("name =~ /^yvonne$/i", vec![true, false]),
+            ("name =~ /^ y v o n n e $/ix", vec![true, false]),
+            ("name =~ /a\\/b/", vec![false, true]),
+        ] {
+            assert_eq!(
+                &eval_all(*backend, formula, &[(0.0, "Yvonne"), (0.0, "a/b")]),
+                expected,
+                "{:?}: {}",
+                backend,
+                formula
+            );
+        }
+    }
+}
+
+#[test]
+fn invalid_regexes_fail_to_compile() {
+    for backend in &[Backend::Interpreter, Backend::Wasm] {
+        let mut context = Context::with_backend(*backend).unwrap();
+        context.define_var("name");
+        // Also when the simplifier could drop the regex
+        for formula in &["name =~ /(/", "name =~ /a{2,1}/ or true"] {
+            let term = parse_formula(formula).unwrap();
+            match context.compile(&term) {
+                Err(Error::InvalidRegex(_)) => {}
+                Err(e) => panic!("{:?}: {}: unexpected error {:?}", backend, formula, e),
+                Ok(_) => panic!("{:?}: {}: compiled", backend, formula),
+            }
+        }
+    }
+}
+
+/// Three-valued results of formulas on x, which is never set and thus
+/// null, and name, which is "Hans"
+static NULLS: &[(&str, Option<bool>)] = &[
+    ("x == 1", None),
+    ("not (x > 1)", None),
+    ("x", None),
+    ("x in (1, 2)", None),
+    ("(name == 'Hans') in (true)", Some(true)),
+    ("(x is null) in (false)", Some(false)),
+    ("x is null", Some(true)),
+    ("name is not null", Some(true)),
+    ("(x > 1) is null", Some(true)),
+    ("x > 1 or name == 'Hans'", Some(true)),
+    ("x > 1 and name == 'Hans'", None),
+    ("x > 1 and name == 'Yuki'", Some(false)),
+    ("name =~ /^H/ or x", Some(true)),
+    ("(x == 1) == (name == 'Hans')", None),
+    ("(x is null) == (name == 'Hans')", Some(true)),
+];
+
+#[test]
+fn null_values_are_unknown() {
+    for backend in &[Backend::Interpreter, Backend::Wasm] {
+        for (formula, expected) in NULLS {
+            let mut context = Context::with_backend(*backend).unwrap();
+            context.define_var("x");
+            context.define_var("name");
+            let term = parse_formula(formula).unwrap();
+            let compiled = context.compile(&term).unwrap();
+            let instance = compiled.instantiate().unwrap();
+            instance
+                .get_variable("name")
+                .unwrap()
+                .set_string("Hans")
+                .unwrap();
+            assert_eq!(
+                instance.evaluate(),
+                Ok(*expected),
+                "{:?}: {}",
+                backend,
+                formula
+            );
+            // run treats unknown as false, like a WHERE clause
+            assert_eq!(
+                instance.run(),
+                Ok(*expected == Some(true)),
+                "{:?}: {}",
+                backend,
+                formula
+            );
+        }
+    }
+}
+
+#[test]
+fn variables_can_be_set_to_null() {
+    for backend in &[Backend::Interpreter, Backend::Wasm] {
+        let mut context = Context::with_backend(*backend).unwrap();
+        context.define_var("x");
+        context.define_var_typed("n", Type::I64);
+        let term = parse_formula("x == 1 or n / 0 > 1").unwrap();
+        let compiled = context.compile(&term).unwrap();
+        let instance = compiled.instantiate().unwrap();
+        let mut x = instance.get_variable("x").unwrap();
+        let mut n = instance.get_typed_variable::<i64>("n").unwrap();
+
+        x.set_f64(1.0).unwrap();
+        assert_eq!(instance.evaluate(), Ok(Some(true)), "{:?}", backend);
+        // n is null, so it is not divided by zero
+        x.set_null().unwrap();
+        assert_eq!(instance.evaluate(), Ok(None), "{:?}", backend);
+        n.set(5).unwrap();
+        assert_eq!(
+            instance.evaluate(),
+            Err(Error::DivisionByZero),
+            "{:?}",
+            backend
+        );
+        n.set_null().unwrap();
+        x.set_f64(2.0).unwrap();
+        assert_eq!(instance.evaluate(), Ok(None), "{:?}", backend);
+    }
+}
+
+/// Compile rules whose source is dropped right after parsing, along
+/// with the context they were compiled in
+fn compile_rules(backend: Backend, rules: &[(u32, String)]) -> HashMap<u32, InstantiatedTerm> {
+    let mut context = Context::with_backend(backend).unwrap();
+    context.define_var("x");
+    rules
+        .iter()
+        .map(|(id, source)| {
+            let term = parse_formula(&source.clone()).unwrap().into_owned();
+            let compiled = context.compile(&term).unwrap();
+            (*id, compiled.instantiate().unwrap())
+        })
+        .collect()
+}
+
+#[test]
+fn compiled_formulas_can_be_kept() {
+    for backend in &[Backend::Interpreter, Backend::Wasm] {
+        let rules = compile_rules(
+            *backend,
+            &[(1, "x > 1".to_string()), (2, "x =~ /^Y/".to_string())],
+        );
+        rules[&1].get_variable("x").unwrap().set_f64(2.0).unwrap();
+        rules[&2]
+            .get_variable("x")
+            .unwrap()
+            .set_string("Yuki")
+            .unwrap();
+        assert_eq!(rules[&1].run(), Ok(true), "{:?}", backend);
+        assert_eq!(rules[&2].run(), Ok(true), "{:?}", backend);
+    }
+}
+
+#[test]
+fn formulas_of_a_context_are_independent() {
+    for backend in &[Backend::Interpreter, Backend::Wasm] {
+        let mut context = Context::with_backend(*backend).unwrap();
+        context.define_var("x");
+        let term = parse_formula("x =~ /^Y/ or x > 1").unwrap();
+        let first = context.compile(&term).unwrap();
+        let second = context.compile(&term).unwrap();
+        // Temporaries do not pile up from one compilation to the next
+        assert_eq!(first.wasm_bytes(), second.wasm_bytes(), "{:?}", backend);
+
+        let first = first.instantiate().unwrap();
+        let second = second.instantiate().unwrap();
+        first.get_variable("x").unwrap().set_f64(2.0).unwrap();
+        second.get_variable("x").unwrap().set_f64(0.0).unwrap();
+        assert_eq!(first.run(), Ok(true), "{:?}", backend);
+        assert_eq!(second.run(), Ok(false), "{:?}", backend);
+    }
+}
+
+#[test]
+fn formulas_are_not_limited_in_slots() {
+    // Every literal takes a slot of the runtime state. Compiling the
+    // long chain recurses deeply, so it gets a thread with a big stack.
+    let formula = (0..1500)
+        .map(|i| format!("name == 'n{}'", i))
+        .collect::<Vec<_>>()
+        .join(" or ");
+    std::thread::Builder::new()
+        .stack_size(256 << 20)
+        .spawn(move || {
+            for backend in &[Backend::Interpreter, Backend::Wasm] {
+                assert_eq!(
+                    eval_all(*backend, &formula, &[(0.0, "n1499"), (0.0, "m")]),
+                    vec![true, false],
+                    "{:?}",
+                    backend
+                );
+            }
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn instances_of_a_formula_are_independent() {
+    for backend in &[Backend::Interpreter, Backend::Wasm] {
+        let mut context = Context::with_backend(*backend).unwrap();
+        context.define_var("x");
+        context.define_var_typed("n", Type::I64);
+        let term = parse_formula("x > 1 and n == 3").unwrap();
+        let compiled = context.compile(&term).unwrap();
+
+        let first = compiled.instantiate().unwrap();
+        first.get_variable("x").unwrap().set_f64(2.0).unwrap();
+        first
+            .get_typed_variable::<i64>("n")
+            .unwrap()
+            .set(3)
+            .unwrap();
+        // Instantiating again leaves the first instance's variables set
+        let second = compiled.instantiate().unwrap();
+        assert_eq!(first.run(), Ok(true), "{:?}", backend);
+        assert_eq!(second.evaluate(), Ok(None), "{:?}", backend);
+
+        second.get_variable("x").unwrap().set_f64(0.0).unwrap();
+        second
+            .get_typed_variable::<i64>("n")
+            .unwrap()
+            .set(3)
+            .unwrap();
+        assert_eq!(first.run(), Ok(true), "{:?}", backend);
+        assert_eq!(second.run(), Ok(false), "{:?}", backend);
+    }
+}
+
+#[test]
+fn compiled_formulas_can_be_instantiated_on_other_threads() {
+    for backend in &[Backend::Interpreter, Backend::Wasm] {
+        let mut context = Context::with_backend(*backend).unwrap();
+        context.define_var("x");
+        let compiled = context.compile(&parse_formula("x > 1").unwrap()).unwrap();
+        let result = std::thread::spawn(move || {
+            let instance = compiled.instantiate().unwrap();
+            instance.get_variable("x").unwrap().set_f64(2.0).unwrap();
+            instance.run()
+        })
+        .join()
+        .unwrap();
+        assert_eq!(result, Ok(true), "{:?}", backend);
+    }
+}
+
+#[test]
+fn untyped_orderings_compare_numbers_and_texts() {
+    for backend in &[Backend::Interpreter, Backend::Wasm] {
+        let mut context = Context::with_backend(*backend).unwrap();
+        context.define_var("a");
+        context.define_var("b");
+        let term = parse_formula("a < b").unwrap();
+        let compiled = context.compile(&term).unwrap();
+        let instance = compiled.instantiate().unwrap();
+        let mut a = instance.get_variable("a").unwrap();
+        let mut b = instance.get_variable("b").unwrap();
+
+        a.set_i64(9007199254740992).unwrap();
+        b.set_i64(9007199254740993).unwrap();
+        assert_eq!(instance.run(), Ok(true), "{:?}", backend);
+        b.set_f64(2.5).unwrap();
+        a.set_i64(2).unwrap();
+        assert_eq!(instance.run(), Ok(true), "{:?}", backend);
+        a.set_string("abc").unwrap();
+        b.set_string("abd").unwrap();
+        assert_eq!(instance.run(), Ok(true), "{:?}", backend);
+        b.set_string("ab").unwrap();
+        assert_eq!(instance.run(), Ok(false), "{:?}", backend);
+        // Numbers and texts are not ordered
+        b.set_i64(1).unwrap();
+        assert_eq!(instance.run(), Ok(false), "{:?}", backend);
+
+        // The last row of a batch stays assigned
+        let results = instance
+            .run_batch(&[("a", Column::String(&["x", "b"]))])
+            .unwrap();
+        assert_eq!(results, vec![false, false], "{:?}", backend);
+        b.set_string("c").unwrap();
+        assert_eq!(instance.run(), Ok(true), "{:?}", backend);
+    }
+}
@@ -0,0 +1,478 @@
+extern crate wormula;
+
+use wormula::evaluator::{Backend, Context};
+use wormula::parse::{parse, parse_formula};
+use wormula::simplify::simplify;
+use wormula::term::Term::*;
+use wormula::term::{RegexFlags, Term};
+use wormula::Error;
+
+fn p(formula: &str) -> Term {
+    let (rest, term) = parse(formula).expect(formula);
+    assert_eq!(rest, "", "unparsed input for {}", formula);
+    term
+}
+
+fn v(name: &str) -> Box<Term> {
+    Box::new(Variable(name.into()))
+}
+
+fn f(n: f64) -> Box<Term<'static>> {
+    Box::new(Float(n))
+}
+
+fn i(n: i64) -> Box<Term<'static>> {
+    Box::new(Int(n))
+}
+
+#[test]
+fn or_is_left_associative() {
+    assert_eq!(p("a or b or c"), Or(Box::new(Or(v("a"), v("b"))), v("c")));
+}
+
+#[test]
+fn and_is_left_associative() {
+    assert_eq!(
+        p("a and b and c"),
+        And(Box::new(And(v("a"), v("b"))), v("c"))
+    );
+}
+
+#[test]
+fn comparisons_are_left_associative() {
+    assert_eq!(p("a == b == c"), Eq(Box::new(Eq(v("a"), v("b"))), v("c")));
+    assert_eq!(
+        p("a != b != c"),
+        Not(Box::new(Eq(
+            Box::new(Not(Box::new(Eq(v("a"), v("b"))))),
+            v("c")
+        )))
+    );
+    assert_eq!(p("a < b < c"), Lt(Box::new(Lt(v("a"), v("b"))), v("c")));
+    assert_eq!(p("a <= b <= c"), Le(Box::new(Le(v("a"), v("b"))), v("c")));
+    assert_eq!(p("a > b > c"), Gt(Box::new(Gt(v("a"), v("b"))), v("c")));
+    assert_eq!(p("a >= b >= c"), Ge(Box::new(Ge(v("a"), v("b"))), v("c")));
+}
+
+#[test]
+fn not_is_right_associative() {
+    assert_eq!(p("not not a"), Not(Box::new(Not(v("a")))));
+}
+
+#[test]
+fn and_binds_tighter_than_or() {
+    assert_eq!(
+        p("a == 1 or b == 2 and c == 3"),
+        Or(
+            Box::new(Eq(v("a"), i(1))),
+            Box::new(And(Box::new(Eq(v("b"), i(2))), Box::new(Eq(v("c"), i(3)))))
+        )
+    );
+    assert_eq!(
+        p("a == 1 and b == 2 or c == 3"),
+        Or(
+            Box::new(And(Box::new(Eq(v("a"), i(1))), Box::new(Eq(v("b"), i(2))))),
+            Box::new(Eq(v("c"), i(3)))
+        )
+    );
+}
+
+#[test]
+fn not_binds_tighter_than_comparison() {
+    assert_eq!(p("not a == b"), Eq(Box::new(Not(v("a"))), v("b")));
+}
+
+#[test]
+fn parentheses_override_precedence() {
+    assert_eq!(
+        p("(a == 1 or b == 2) and c == 3"),
+        And(
+            Box::new(Or(Box::new(Eq(v("a"), i(1))), Box::new(Eq(v("b"), i(2))))),
+            Box::new(Eq(v("c"), i(3)))
+        )
+    );
+    assert_eq!(p("a or (b or c)"), Or(v("a"), Box::new(Or(v("b"), v("c")))));
+    assert_eq!(p("not (a == b)"), Not(Box::new(Eq(v("a"), v("b")))));
+    assert_eq!(p("( ( a ) )"), Variable("a".into()));
+}
+
+#[test]
+fn arithmetic_precedence() {
+    assert_eq!(
+        p("a + b * c > 1000"),
+        Gt(
+            Box::new(Add(v("a"), Box::new(Mul(v("b"), v("c"))))),
+            i(1000)
+        )
+    );
+    assert_eq!(
+        p("a - b - c % d"),
+        Sub(Box::new(Sub(v("a"), v("b"))), Box::new(Mod(v("c"), v("d"))))
+    );
+    assert_eq!(p("(a + b) * c"), Mul(Box::new(Add(v("a"), v("b"))), v("c")));
+}
+
+#[test]
+fn unary_minus() {
+    assert_eq!(p("-a * b"), Mul(Box::new(Neg(v("a"))), v("b")));
+    assert_eq!(p("- 3"), Neg(i(3)));
+    assert_eq!(p("a -3"), Sub(v("a"), i(3)));
+    assert_eq!(p("a - -3"), Sub(v("a"), i(-3)));
+}
+
+#[test]
+fn slash_after_an_operand_divides() {
+    assert_eq!(p("a / b / c"), Div(Box::new(Div(v("a"), v("b"))), v("c")));
+    assert_eq!(
+        p("a / 2 =~ /x/"),
+        Matches(
+            Box::new(Div(v("a"), i(2))),
+            Box::new(Regex("x".into(), RegexFlags::default()))
+        )
+    );
+}
+
+#[test]
+fn match_operators() {
+    let re = || Box::new(Regex("^Y".into(), RegexFlags::default()));
+    assert_eq!(p("name =~ /^Y/"), Matches(v("name"), re()));
+    assert_eq!(p("name !~ /^Y/"), Not(Box::new(Matches(v("name"), re()))));
+    assert_eq!(
+        p("a =~ /^Y/ and b"),
+        And(Box::new(Matches(v("a"), re())), v("b"))
+    );
+}
+
+#[test]
+fn membership_operators() {
+    let list = || vec![String("DE".into()), Int(3), Float(2.5), Bool(true)];
+    assert_eq!(
+        p("country in (\"DE\", 3, 2.5, true)"),
+        In(v("country"), list())
+    );
+    assert_eq!(
+        p("country not in ('DE', 3, 2.5, true,)"),
+        Not(Box::new(In(v("country"), list())))
+    );
+    assert_eq!(
+        p("a + 1 in (2) and b"),
+        And(
+            Box::new(In(Box::new(Add(v("a"), i(1))), vec![Int(2)])),
+            v("b")
+        )
+    );
+    assert_eq!(p("index == 1"), Eq(v("index"), i(1)));
+}
+
+#[test]
+fn parse_formula_reports_bad_lists() {
+    let e = parse_formula("a in 1").unwrap_err();
+    assert_eq!((e.expected, e.offset), ("list", 5));
+    let e = parse_formula("a in (b)").unwrap_err();
+    assert_eq!((e.expected, e.offset), ("literal", 6));
+    let e = parse_formula("a in (1, 2").unwrap_err();
+    assert_eq!(e.expected, "closing parenthesis");
+}
+
+#[test]
+fn integer_literals_are_exact() {
+    assert_eq!(p("9007199254740993"), Int(9_007_199_254_740_993));
+    assert_eq!(p("-9223372036854775808"), Int(i64::min_value()));
+    assert_eq!(p("1_000_000"), Int(1_000_000));
+    assert_eq!(p("0xff_FF"), Int(0xffff));
+    assert_eq!(p("0B1010"), Int(10));
+    assert_eq!(p("-0x10"), Int(-16));
+}
+
+#[test]
+fn numbers_with_fraction_or_exponent_are_floats() {
+    assert_eq!(p("a == 1.5"), Eq(v("a"), f(1.5)));
+    assert_eq!(p("a == 1e3"), Eq(v("a"), f(1000.0)));
+    assert_eq!(p("a == -2.5E-1"), Eq(v("a"), f(-0.25)));
+}
+
+#[test]
+fn parse_formula_reports_integer_overflow() {
+    let e = parse_formula("id == 9223372036854775808").unwrap_err();
+    assert_eq!(e.offset, 6);
+    assert_eq!(e.len, 19);
+    assert_eq!(e.expected, "integer within range");
+    let e = parse_formula("id == 0x").unwrap_err();
+    assert_eq!(e.expected, "digits");
+}
+
+#[test]
+fn boolean_literals_and_negation() {
+    assert_eq!(
+        p("flag == true or false"),
+        Or(
+            Box::new(Eq(v("flag"), Box::new(Bool(true)))),
+            Box::new(Bool(false))
+        )
+    );
+    assert_eq!(
+        p("!a and not b"),
+        And(Box::new(Not(v("a"))), Box::new(Not(v("b"))))
+    );
+    assert_eq!(
+        p("!(a != 1)"),
+        Not(Box::new(Not(Box::new(Eq(v("a"), i(1))))))
+    );
+    assert_eq!(p("trueish"), Variable("trueish".into()));
+}
+
+#[test]
+fn keywords_need_a_word_boundary() {
+    assert_eq!(p("a == orange"), Eq(v("a"), v("orange")));
+    assert_eq!(p("android == 1"), Eq(v("android"), i(1)));
+    assert_eq!(p("notes == 1"), Eq(v("notes"), i(1)));
+    assert_eq!(p("not_deleted"), Variable("not_deleted".into()));
+    assert_eq!(p("true_count > 1"), Gt(v("true_count"), i(1)));
+    assert_eq!(p("null_ok or in_stock"), Or(v("null_ok"), v("in_stock")));
+    assert_eq!(p("a == or_else"), Eq(v("a"), v("or_else")));
+}
+
+#[test]
+fn null_tests() {
+    assert_eq!(p("x is null"), IsNull(v("x")));
+    assert_eq!(
+        p("a.b is not null and c"),
+        And(Box::new(Not(Box::new(IsNull(v("a.b"))))), v("c"))
+    );
+    assert_eq!(p("x + 1 is null"), IsNull(Box::new(Add(v("x"), i(1)))));
+    assert_eq!(p("island == nullable"), Eq(v("island"), v("nullable")));
+    let e = parse_formula("x is nil").unwrap_err();
+    assert_eq!((e.expected, e.offset), ("null", 5));
+}
+
+/// The string literal ```formula``` decodes to
+fn string(formula: &str) -> std::string::String {
+    match p(formula) {
+        String(s) => s.into_owned(),
+        other => panic!("{}: expected a string, got {:?}", formula, other),
+    }
+}
+
+#[test]
+fn identifiers_may_be_record_paths() {
+    assert_eq!(p("user_id == 1"), Eq(v("user_id"), i(1)));
+    assert_eq!(p("_private"), Variable("_private".into()));
+    assert_eq!(p("http.status >= 500"), Ge(v("http.status"), i(500)));
+    assert_eq!(
+        p("tags[0] == 'a' or items[12].price.net>1"),
+        Or(
+            Box::new(Eq(v("tags[0]"), Box::new(String("a".into())))),
+            Box::new(Gt(v("items[12].price.net"), i(1)))
+        )
+    );
+    assert_eq!(p("a.and"), Variable("a.and".into()));
+}
+
+#[test]
+fn parse_formula_reports_bad_paths() {
+    let e = parse_formula("http. == 1").unwrap_err();
+    assert_eq!((e.expected, e.offset), ("field name", 5));
+    let e = parse_formula("tags[x]").unwrap_err();
+    assert_eq!((e.expected, e.offset), ("index", 5));
+    let e = parse_formula("tags[1 == 1").unwrap_err();
+    assert_eq!((e.expected, e.offset), ("closing bracket", 6));
+}
+
+#[test]
+fn string_escapes_are_decoded() {
+    assert_eq!(string(r#""plain""#), "plain");
+    assert_eq!(string(r#""say \"hi\"""#), "say \"hi\"");
+    assert_eq!(string(r#""a\\b""#), "a\\b");
+    assert_eq!(string(r#""\n\t\r\0""#), "\n\t\r\0");
+    assert_eq!(string(r#""\u{e9}t\u{E9} \u{1F600}""#), "été \u{1F600}");
+}
+
+#[test]
+fn strings_may_be_single_quoted() {
+    assert_eq!(string("'abc'"), "abc");
+    assert_eq!(string(r#"'it\'s "quoted"'"#), "it's \"quoted\"");
+    assert_eq!(string(r#""it's""#), "it's");
+}
+
+#[test]
+fn regex_literals_take_flags_and_escaped_slashes() {
+    let flags = |letters: &str| {
+        let mut flags = RegexFlags::default();
+        for letter in letters.chars() {
+            assert!(flags.set(letter));
+        }
+        flags
+    };
+    assert_eq!(p("/^a/"), Regex("^a".into(), flags("")));
+    assert_eq!(p("/^a/i"), Regex("^a".into(), flags("i")));
+    assert_eq!(p("/a.b/smxi"), Regex("a.b".into(), flags("imsx")));
+    assert_eq!(p(r"/a\/b/"), Regex("a/b".into(), flags("")));
+    // Other escapes are passed on to the regex engine
+    assert_eq!(p(r"/\d+\.\\/"), Regex(r"\d+\.\\".into(), flags("")));
+}
+
+#[test]
+fn parse_formula_reports_bad_regexes() {
+    let e = parse_formula("a == /x/q").unwrap_err();
+    assert_eq!((e.expected, e.offset), ("regex flag", 8));
+    let e = parse_formula(r"a == /x\/").unwrap_err();
+    assert_eq!((e.expected, e.offset), ("closing slash", 9));
+    let e = parse_formula("a == //").unwrap_err();
+    assert_eq!((e.expected, e.offset), ("regex", 6));
+}
+
+#[test]
+fn formulas_are_printed_reparseably() {
+    for formula in &[
+        "a == 1 and (b or c)",
+        "(a or b) and c",
+        "a - (b - c) * -d",
+        "-(5) + -(1.5) * -x",
+        "not (a == b) or a != b",
+        "(a < b) == c",
+        "name == \"say \\\"hi\\\"\\n\"",
+        "x == 'it\\'s' and y =~ /^a/",
+        "2.0 * 1e300 > 0",
+        r"x !~ /a\/b\\/im",
+        "x in (1, 'a', -2.5) and y not in (true)",
+        "(a in (1)) == b",
+        "http.status == 200 and tags[0].name != user_id",
+        "x is null or y.z is not null",
+        "(a is null) == b",
+        "x < 1e999 and y > -1e999",
+    ] {
+        let term = p(formula);
+        assert_eq!(
+            p(&term.to_string()),
+            term,
+            "{} printed as {}",
+            formula,
+            term
+        );
+    }
+    assert_eq!(p("a=='x\\ty'").to_string(), "a == \"x\\ty\"");
+    assert_eq!(p("a-(b-c)").to_string(), "a - (b - c)");
+    assert_eq!(p(r"a=~/\//xs").to_string(), r"a =~ /\//sx");
+    assert_eq!(p("not (a is null)").to_string(), "a is not null");
+    let overflowed = simplify(&p("x < 1e308 * 10 or x == -(1e308 * 10)"));
+    assert_eq!(p(&overflowed.to_string()), overflowed);
+    let nan = simplify(&p("x != 0.0 / 0.0"));
+    assert_eq!(p(&nan.to_string()), p("x != 0.0 / 0.0"));
+}
+
+#[test]
+fn whitespace_and_comments_separate_tokens() {
+    let expected = p("a == 1 and not (b in (2, 3) or c =~ /x/)");
+    for formula in &[
+        "  a==1 and not(b in(2,3)or c=~/x/)  ",
+        "\n\ta ==\n 1\r\nand not ( b in ( 2 , 3 ) or c =~ /x/ )\n",
+        "# rule 7\na == 1 # must be one\nand not (b in (2, 3) # or 4\n or c =~ /x/)",
+        "/* a */ a /**/ == /* b */ 1 and not /*(*/ (b in (/* c */ 2, 3) or c =~ /* d */ /x/)",
+        "a == 1 and not (b in (2, 3) or c =~ /x/) # trailing comment",
+    ] {
+        assert_eq!(p(formula), expected, "{:?}", formula);
+        assert_eq!(
+            parse_formula(formula),
+            Ok(expected.clone()),
+            "{:?}",
+            formula
+        );
+    }
+    // A slash directly after an operand still divides
+    assert_eq!(p("a/b"), Div(v("a"), v("b")));
+    assert_eq!(p("a /b/ c"), Div(Box::new(Div(v("a"), v("b"))), v("c")));
+}
+
+#[test]
+fn parse_formula_reports_positions_after_comments() {
+    let e = parse_formula("/* first\n   rule */ a == 1 and\n# comment\n  b ==").unwrap_err();
+    assert_eq!(e.expected, "value");
+    assert_eq!((e.offset, e.line, e.column), (47, 4, 7));
+
+    let e = parse_formula("a == 1 /* unterminated\n and b").unwrap_err();
+    assert_eq!(e.expected, "end of comment");
+    assert_eq!((e.offset, e.line, e.column), (7, 1, 8));
+
+    let e = parse_formula("a == 1 # comment\n b").unwrap_err();
+    assert_eq!(e.expected, "operator or end of input");
+    assert_eq!((e.line, e.column), (2, 2));
+}
+
+#[test]
+fn parse_formula_rejects_trailing_input() {
+    let e = parse_formula("a == 1 b").unwrap_err();
+    assert_eq!(e.offset, 7);
+    assert_eq!(e.len, 1);
+    assert_eq!(e.expected, "operator or end of input");
+}
+
+#[test]
+fn parse_formula_reports_unterminated_string() {
+    let e = parse_formula("a == 1 and\nname == \"abc").unwrap_err();
+    assert_eq!(e.expected, "closing quote");
+    assert_eq!((e.line, e.column), (2, 10));
+    assert_eq!(e.line_text, "name == \"abc");
+}
+
+#[test]
+fn parse_formula_reports_bad_escapes() {
+    let e = parse_formula(r#"a == "x\q""#).unwrap_err();
+    assert_eq!(e.expected, "escape sequence");
+    assert_eq!(e.offset, 7);
+
+    for formula in &[
+        r#"a == "\u{}""#,
+        r#"a == "\u{d800}""#,
+        r#"a == "\u{1234567}""#,
+    ] {
+        let e = parse_formula(formula).unwrap_err();
+        assert_eq!(e.expected, "unicode escape", "{}", formula);
+        assert_eq!(e.offset, 6, "{}", formula);
+    }
+
+    let e = parse_formula("a == 'abc\\'").unwrap_err();
+    assert_eq!(e.expected, "closing quote");
+}
+
+#[test]
+fn parse_formula_reports_missing_value() {
+    let e = parse_formula("a == ").unwrap_err();
+    assert_eq!(e.expected, "value");
+    assert_eq!(e.offset, 5);
+
+    let e = parse_formula("(a == 1 or b == 2").unwrap_err();
+    assert_eq!(e.expected, "closing parenthesis");
+}
+
+#[test]
+fn parse_error_renders_caret_snippet() {
+    let e = parse_formula("age >= 18 and and").unwrap_err();
+    assert_eq!(
+        e.render(),
+        "line 1, column 15: expected value\n1 | age >= 18 and and\n  |               ^^^"
+    );
+}
+
+#[test]
+fn terms_can_outlive_their_source() {
+    let owned: Term<'static> = {
+        let source = "x in ('a', 1) and y =~ /b/i or not z is null".to_string();
+        p(&source).into_owned()
+    };
+    assert_eq!(owned, p("x in ('a', 1) and y =~ /b/i or not z is null"));
+}
+
+#[test]
+fn unknown_variables_of_owned_terms_are_located() {
+    let source = "x == 1 or (y > 2 and x < 3)";
+    let owned = p(source).into_owned();
+    let mut context = Context::with_backend(Backend::Interpreter).unwrap();
+    context.define_var("y");
+    match context.compile_with_source(&owned, source) {
+        Err(Error::UnknownVariable(idents)) => {
+            let located: Vec<_> = idents.iter().map(|i| (&i.name[..], i.offset)).collect();
+            assert_eq!(located, vec![("x", Some(0)), ("x", Some(21))]);
+        }
+        other => panic!("expected unknown variables, got {:?}", other.err()),
+    }
+}
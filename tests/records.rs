@@ -0,0 +1,77 @@
+extern crate wormula;
+
+use wormula::evaluator::{Backend, Context, Type};
+use wormula::parse::parse_formula;
+use wormula::record::Value;
+use wormula::Error;
+
+fn request(status: i64, tags: Vec<&str>) -> Value {
+    Value::record(vec![
+        ("user_id", Value::from(42)),
+        (
+            "http",
+            Value::record(vec![
+                ("status", Value::from(status)),
+                ("method", Value::from("GET")),
+            ]),
+        ),
+        ("tags", Value::from(tags)),
+    ])
+}
+
+#[test]
+fn paths_resolve_nested_values() {
+    let record = Value::record(vec![(
+        "items",
+        Value::List(vec![Value::record(vec![("price", Value::from(2.5))])]),
+    )]);
+    assert_eq!(record.get("items[0].price"), Some(&Value::F64(2.5)));
+    assert_eq!(record.get("items[1].price"), None);
+    assert_eq!(record.get("items.price"), None);
+    assert_eq!(record.get("items[0].price.net"), None);
+    assert_eq!(
+        record.get("items[0]"),
+        Some(&Value::record(vec![("price", Value::from(2.5))]))
+    );
+}
+
+#[test]
+fn variables_are_set_from_records() {
+    for backend in &[Backend::Interpreter, Backend::Wasm] {
+        let mut context = Context::with_backend(*backend).unwrap();
+        context.define_var("user_id");
+        context.define_var_typed("http.status", Type::I64);
+        context.define_var("http.method");
+        context.define_var("tags[1]");
+        let term =
+            parse_formula("user_id == 42 and http.status >= 500 and tags[1] =~ /^b/").unwrap();
+        let compiled = context.compile(&term).unwrap();
+        let instance = compiled.instantiate().unwrap();
+
+        instance.set_record(&request(503, vec!["a", "b"])).unwrap();
+        assert_eq!(instance.run(), Ok(true), "{:?}", backend);
+        instance.set_record(&request(200, vec!["a", "b"])).unwrap();
+        assert_eq!(instance.run(), Ok(false), "{:?}", backend);
+        instance.set_record(&request(503, vec!["b", "a"])).unwrap();
+        assert_eq!(instance.run(), Ok(false), "{:?}", backend);
+
+        // A missing field leaves its variable null
+        instance.set_record(&request(503, vec!["a"])).unwrap();
+        assert_eq!(instance.evaluate(), Ok(None), "{:?}", backend);
+        assert_eq!(instance.run(), Ok(false), "{:?}", backend);
+    }
+}
+
+#[test]
+fn records_are_type_checked() {
+    let mut context = Context::with_backend(Backend::Interpreter).unwrap();
+    context.define_var_typed("http.status", Type::String);
+    context.define_var("http");
+    let term = parse_formula("http.status == 'ok'").unwrap();
+    let compiled = context.compile(&term).unwrap();
+    let instance = compiled.instantiate().unwrap();
+    match instance.set_record(&request(200, vec![])) {
+        Err(Error::TypeMismatch(_)) => {}
+        other => panic!("expected a type mismatch, got {:?}", other),
+    }
+}
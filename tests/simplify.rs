@@ -0,0 +1,93 @@
+extern crate wormula;
+
+use wormula::parse::parse_formula;
+use wormula::simplify::simplify;
+use wormula::term::Term;
+
+/// Simplify ```formula``` and compare it with the parsed ```expected```
+fn s(formula: &str, expected: &str) {
+    let simplified = simplify(&parse_formula(formula).unwrap());
+    assert_eq!(simplified, parse_formula(expected).unwrap(), "{}", formula);
+}
+
+/// Simplify ```formula``` to a constant
+fn c(formula: &str) -> Term {
+    simplify(&parse_formula(formula).unwrap())
+}
+
+#[test]
+fn literal_comparisons_are_folded() {
+    assert_eq!(c("1 == 1"), Term::Bool(true));
+    assert_eq!(c("1 < 0.5"), Term::Bool(false));
+    assert_eq!(c("\"abc\" >= \"abb\""), Term::Bool(true));
+    assert_eq!(c("\"Hans\" =~ /^H/"), Term::Bool(true));
+    assert_eq!(c("1 == 1 and 2 > 3"), Term::Bool(false));
+    assert_eq!(c("not (1 == 1)"), Term::Bool(false));
+}
+
+#[test]
+fn constants_are_propagated() {
+    s("1 == 1 and x == 5", "x == 5");
+    assert_eq!(c("x == 5 or 1 == 1"), Term::Bool(true));
+    assert_eq!(c("x == 5 and 1 == 2"), Term::Bool(false));
+    s("(2 < 1 or x == 5) and y", "x == 5 and y");
+}
+
+#[test]
+fn double_negation_is_removed() {
+    s("not not (x == 5)", "x == 5");
+    s("not not (x == 5 and y)", "x == 5 and y");
+    s("not not not x", "not x");
+    // A value compared to 1 is not necessarily a boolean
+    s("not not x == 1", "(not not x) == 1");
+}
+
+#[test]
+fn chains_compared_as_values_keep_their_operands() {
+    s("(true and x) == 5", "(true and x) == 5");
+    s("(x or x) == 5", "(x or x) == 5");
+    s("(1 == 1 and x == 2) == false", "(x == 2) == false");
+    assert_eq!(c("(false and x) == false"), Term::Bool(true));
+}
+
+#[test]
+fn repeated_operands_are_removed() {
+    s("x == 1 and y and x == 1", "x == 1 and y");
+    s("x == 1 or (x == 1 or y)", "x == 1 or y");
+    s("(a and b) or (a and b)", "a and b");
+    s("x in (1, 'a', 1, 2, 'a')", "x in (1, 'a', 2)");
+}
+
+#[test]
+fn literal_memberships_are_folded() {
+    assert_eq!(c("2 in (1, 2.0, 3)"), Term::Bool(true));
+    assert_eq!(c("'b' not in ('a', 'c')"), Term::Bool(true));
+    s("x == 1 and 'a' in ('a')", "x == 1");
+}
+
+#[test]
+fn comparisons_of_incompatible_literals_are_kept() {
+    // The type checker rejects these, they must not become false
+    s("1 in ('1', true)", "1 in ('1', true)");
+    s("1 == 'a'", "1 == 'a'");
+    s("true < false", "true < false");
+    s("'a' >= 1.5", "'a' >= 1.5");
+    assert_eq!(c("0.0 / 0.0 < 1"), Term::Bool(false));
+}
+
+#[test]
+fn tautologies_are_eliminated() {
+    assert_eq!(c("x is null or not (x is null)"), Term::Bool(true));
+    assert_eq!(c("y and x is null and x is not null"), Term::Bool(false));
+    // Not a tautology if x is not a boolean
+    s("x or not x", "x or not x");
+    // Nor if x is null, which makes the comparison unknown
+    s("x > 1 or not (x > 1)", "x > 1 or not (x > 1)");
+}
+
+#[test]
+fn null_tests_of_literals_are_folded() {
+    assert_eq!(c("1 is null"), Term::Bool(false));
+    assert_eq!(c("'a' is not null"), Term::Bool(true));
+    s("x == 1 or 2.5 is null", "x == 1");
+}
@@ -0,0 +1,116 @@
+extern crate wormula;
+
+use std::time::{Duration, UNIX_EPOCH};
+use wormula::evaluator::{Backend, Context, Type};
+use wormula::parse::parse_formula;
+use wormula::Error;
+
+/// A context with one variable of every type
+fn typed_context<'a>() -> Context<'a> {
+    let mut context = Context::with_backend(Backend::Interpreter).unwrap();
+    context.define_var_typed("age", Type::I64);
+    context.define_var_typed("score", Type::F64);
+    context.define_var_typed("admin", Type::Bool);
+    context.define_var_typed("name", Type::String);
+    context.define_var_typed("payload", Type::Bytes);
+    context.define_var_typed("created", Type::Timestamp);
+    context.define_var_typed("updated", Type::Timestamp);
+    context.define_var("anything");
+    context
+}
+
+fn check(formula: &str) -> Result<(), Error> {
+    let context = typed_context();
+    let term = parse_formula(formula).unwrap();
+    context.compile(&term).map(|_| ())
+}
+
+#[test]
+fn well_typed_formulas_compile() {
+    for formula in &[
+        "age > 3 and score <= 2.5",
+        "age == score",
+        "admin and name =~ /^a/",
+        "not admin or name > \"m\"",
+        "payload == \"abc\" and payload == name",
+        "created < updated",
+        "anything > 3 and anything == \"x\"",
+        "payload =~ /^a/ and anything !~ /b/",
+        "age in (1, 2.5) and name not in ('a', 'b') and anything in (1, 'x')",
+        "anything",
+        // Bare variables of any type are tested for truthiness
+        "score",
+        "not name",
+        "age and admin",
+        "admin == true",
+        "age is null or name is not null and (admin is null) == false",
+    ] {
+        assert_eq!(check(formula), Ok(()), "{}", formula);
+    }
+}
+
+#[test]
+fn ill_typed_formulas_are_rejected() {
+    for formula in &[
+        "name > 3",
+        "age == \"3\"",
+        "admin < admin",
+        "created > 3",
+        "not (age + 1)",
+        "admin == 1",
+        "age == /3/",
+        "name == /a/",
+        "anything == /a/",
+        "age =~ /3/",
+        "name =~ name",
+        "/a/ =~ name",
+        "anything < admin",
+        "anything == true",
+        "age in (1, \"2\")",
+        "name not in ('a', 3)",
+        "anything in (1, false)",
+    ] {
+        match check(formula) {
+            Err(Error::TypeMismatch(_)) => {}
+            other => panic!("{}: expected a type mismatch, got {:?}", formula, other),
+        }
+    }
+}
+
+#[test]
+fn typed_variables_evaluate() {
+    let context = typed_context();
+    let term = parse_formula("admin and created < updated and payload == \"ok\"").unwrap();
+    let compiled = context.compile(&term).unwrap();
+    let instance = compiled.instantiate().unwrap();
+
+    let mut admin = instance.get_typed_variable::<bool>("admin").unwrap();
+    let mut created = instance.get_typed_variable("created").unwrap();
+    let mut updated = instance.get_typed_variable("updated").unwrap();
+    let mut payload = instance.get_typed_variable::<&[u8]>("payload").unwrap();
+    admin.set(true).unwrap();
+    created.set(UNIX_EPOCH).unwrap();
+    updated.set(UNIX_EPOCH + Duration::from_secs(1)).unwrap();
+    payload.set(b"ok").unwrap();
+    assert_eq!(instance.run(), Ok(true));
+
+    admin.set(false).unwrap();
+    assert_eq!(instance.run(), Ok(false));
+}
+
+#[test]
+fn setters_must_match_the_declared_type() {
+    let context = typed_context();
+    let term = parse_formula("age > 3").unwrap();
+    let compiled = context.compile(&term).unwrap();
+    let instance = compiled.instantiate().unwrap();
+
+    match instance.get_variable("age") {
+        Err(Error::TypeMismatch(msg)) => assert!(msg.contains("get_typed_variable"), "{}", msg),
+        _ => panic!("age is declared with a type"),
+    }
+    match instance.get_typed_variable::<f64>("age") {
+        Err(Error::TypeMismatch(_)) => {}
+        _ => panic!("age is not declared as F64"),
+    }
+}